@@ -1,96 +1,653 @@
 //! Command-line interface for {{PROJECT_NAME}}
 
-use anyhow::Result;
-use clap::{Parser, Subcommand};
-use {{PROJECT_NAME}}_core::{core, types::Config};
-use {{PROJECT_NAME}}_utils::{logging, string};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Cursor, Read, Write};
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand};
+use {{PROJECT_NAME}}_core::{
+    Error,
+    app::{App, BoxFuture, Service},
+    config::{self, ConfigLoader, LoadedConfig},
+    core,
+    error::ErrorCategory,
+    events::EventBus,
+    flags::{FeatureFlags, FlagOverrides},
+    formats::{self, Input, InputFormat, Records},
+    jobs::{Job, JobContext, JobOptions, JobQueue},
+    metrics::MetricsFormat,
+    operations::CancellationToken,
+    pipeline::{OutputFormat, Passthrough, Pipeline, PipelineStats, Processor},
+    reload::ConfigWatcher,
+    report::{ErrorFormat, ErrorReport},
+    snapshot::SnapshotStore,
+    types::{AppState, Config, RateLimit, Record},
+};
+use {{PROJECT_NAME}}_utils::{
+    cache, logging,
+    rate_limit::{self, RateLimiter, SystemClock},
+    string,
+};
 
 #[derive(Parser)]
 #[command(name = "{{PROJECT_NAME}}")]
 #[command(about = "A modern Rust workspace project")]
 #[command(version)]
 struct Cli {
+    #[command(flatten)]
+    options: GlobalOptions,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Args)]
+struct GlobalOptions {
     /// Enable debug mode
     #[arg(short, long)]
     debug: bool,
-    
+
     /// Configuration file path
     #[arg(short, long)]
     config: Option<String>,
-    
-    #[command(subcommand)]
-    command: Commands,
+
+    #[arg(
+        short,
+        long,
+        value_name = "NAME",
+        help = format!("Configuration profile to apply (overrides {})", config::profile_env_var())
+    )]
+    profile: Option<String>,
+
+    /// Format of error reports on stderr: human or json
+    #[arg(long, value_name = "FORMAT", default_value = "human")]
+    error_format: ErrorFormat,
+
+    /// Override a configuration value (repeatable)
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    overrides: Vec<String>,
+
+    /// Turn a feature flag on (repeatable)
+    #[arg(long = "enable-flag", value_name = "FLAG")]
+    enable_flags: Vec<String>,
+
+    /// Turn a feature flag off (repeatable)
+    #[arg(long = "disable-flag", value_name = "FLAG")]
+    disable_flags: Vec<String>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
+    #[command(flatten)]
+    App(AppCommand),
+    #[command(flatten)]
+    Standalone(StandaloneCommand),
+}
+
+/// Commands run by the initialized application
+#[derive(Subcommand)]
+enum AppCommand {
     /// Initialize the project
     Init {
         /// Project name
         #[arg(short, long)]
         name: Option<String>,
     },
-    /// Run the main application
+    /// Process input records
     Run {
-        /// Input file
+        /// Input file; `-` or none reads stdin
         #[arg(short, long)]
         input: Option<String>,
+        /// Output file; none writes to stdout
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Input format: csv, json, ndjson, text or toml (default: detected)
+        #[arg(short, long, value_name = "FORMAT")]
+        format: Option<String>,
     },
     /// Show project status
     Status,
+    /// Inspect feature flags
+    Flags {
+        #[command(subcommand)]
+        command: FlagsCommand,
+    },
+}
+
+/// Commands handled before initialization, so nothing else writes to stdout
+#[derive(Subcommand)]
+enum StandaloneCommand {
+    /// Print operation and error metrics, including previous runs
+    Metrics {
+        /// Output format: prometheus or json
+        #[arg(long, value_name = "FORMAT", default_value = "prometheus")]
+        format: MetricsFormat,
+    },
+    /// Inspect the configuration format
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Manage persistent caches in the user cache directory
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print the JSON Schema of the configuration file
+    Schema,
+}
+
+#[derive(Subcommand)]
+enum FlagsCommand {
+    /// List every feature flag with its effective setting and where it came from
+    List,
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Print entry counts and hit/miss statistics of every cache
+    Stats,
+    /// Delete every cache
+    Clear,
 }
 
-fn main() -> Result<()> {
+/// Name of the `rate_limits` entry throttling the records of `run`
+const RUN_RATE_LIMIT: &str = "run";
+
+/// Job submitted by `run` to stream the input through the pipeline
+///
+/// The input can only be read once, so the job must not be retried.
+struct RunJob {
+    input: Option<Records<'static>>,
+    output: Option<Box<dyn Write + Send>>,
+    output_format: OutputFormat,
+    state: Arc<AppState>,
+    stats: Arc<Mutex<PipelineStats>>,
+}
+
+impl Job for RunJob {
+    fn name(&self) -> &str {
+        "run"
+    }
+
+    fn run(&mut self, ctx: &JobContext) -> {{PROJECT_NAME}}_core::Result<()> {
+        let (Some(input), Some(output)) = (self.input.take(), self.output.take()) else {
+            return Err(Error::Other("input was already consumed".to_string()));
+        };
+        // Every record takes a permit, so the limit paces the whole run. The limit
+        // is looked up per record, so a reloaded configuration applies mid-run.
+        let state = Arc::clone(&self.state);
+        let mut limiter: Option<(RateLimit, Arc<dyn RateLimiter>)> = None;
+        let process = move |record: Record| {
+            let limit = state.config().rate_limits.get(RUN_RATE_LIMIT).copied();
+            if limit != limiter.as_ref().map(|(current, _)| *current) {
+                limiter = limit.map(|limit| {
+                    (
+                        limit,
+                        rate_limit::from_config(&limit, Arc::new(SystemClock)),
+                    )
+                });
+            }
+            if let Some((_, limiter)) = &limiter {
+                limiter.acquire(RUN_RATE_LIMIT);
+            }
+            Passthrough.process(record)
+        };
+        let metrics = self.state.metrics().clone();
+        let stats = Pipeline::new(process)
+            .with_output(self.output_format)
+            .with_cancellation(ctx.token.clone())
+            .on_failure(move |failure| {
+                logging::error(&format!("Record failed: {}", failure));
+                metrics.record_error(&failure.error);
+            })
+            .run(input, output)?;
+        *self.stats.lock().unwrap_or_else(PoisonError::into_inner) = stats;
+        Ok(())
+    }
+}
+
+/// Stdin read on a thread of its own, so that reading stops once `token` is cancelled
+///
+/// The thread itself stays blocked until stdin has more data or is closed.
+struct CancellableStdin {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    chunk: Cursor<Vec<u8>>,
+    token: CancellationToken,
+}
+
+impl CancellableStdin {
+    /// Interval between checks of the token while waiting for input
+    const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+    fn new(token: CancellationToken) -> io::Result<Self> {
+        let (tx, chunks) = mpsc::sync_channel(16);
+        thread::Builder::new()
+            .name("stdin-reader".to_string())
+            .spawn(move || {
+                let mut stdin = io::stdin().lock();
+                loop {
+                    let mut chunk = vec![0; 8 * 1024];
+                    let result = match stdin.read(&mut chunk) {
+                        Ok(0) => break,
+                        Ok(read) => {
+                            chunk.truncate(read);
+                            Ok(chunk)
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => Err(e),
+                    };
+                    let failed = result.is_err();
+                    if tx.send(result).is_err() || failed {
+                        break;
+                    }
+                }
+            })?;
+        Ok(Self {
+            chunks,
+            chunk: Cursor::new(Vec::new()),
+            token,
+        })
+    }
+}
+
+impl Read for CancellableStdin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.chunk.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            if self.token.is_cancelled() {
+                return Err(io::Error::other("reading stdin cancelled"));
+            }
+            match self.chunks.recv_timeout(Self::POLL_INTERVAL) {
+                Ok(chunk) => self.chunk = Cursor::new(chunk?),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+    }
+}
+
+/// Parse records from `path`; `-` or none is stdin, read until `token` is cancelled
+///
+/// The format is `format` if given, else detected from the extension or the content.
+fn open_input(
+    path: Option<&str>,
+    format: Option<&str>,
+    token: CancellationToken,
+) -> Result<(&'static dyn InputFormat, Records<'static>)> {
+    let path = path.filter(|path| *path != "-").map(Path::new);
+    let input: Input<'static> = match path {
+        None => Box::new(BufReader::new(CancellableStdin::new(token)?)),
+        Some(path) => {
+            let file = File::open(path)
+                .map_err(Error::from)
+                .with_context(|| format!("cannot open input {}", path.display()))?;
+            Box::new(BufReader::new(file))
+        }
+    };
+    let (format, input) = match format {
+        Some(name) => (formats::by_name(name)?, input),
+        None => formats::detect(path, input)?,
+    };
+    logging::info(&format!("Reading {} input", format.name()));
+    Ok((format, format.records(input)))
+}
+
+/// Create `path` for writing; none is stdout
+fn open_output(path: Option<&str>) -> Result<Box<dyn Write + Send>> {
+    match path {
+        None => Ok(Box::new(BufWriter::new(io::stdout()))),
+        Some(path) => {
+            let file = File::create(path)
+                .map_err(Error::from)
+                .with_context(|| format!("cannot create output {}", path))?;
+            Ok(Box::new(BufWriter::new(file)))
+        }
+    }
+}
+
+/// Restores state from the snapshot on start and saves it on stop
+struct Snapshots {
+    store: SnapshotStore,
+}
+
+impl Service for Snapshots {
+    fn name(&self) -> &'static str {
+        "snapshot"
+    }
+
+    fn start<'a>(&'a self, state: &'a Arc<AppState>) -> BoxFuture<'a, {{PROJECT_NAME}}_core::Result<()>> {
+        Box::pin(async move {
+            if let Some(snapshot) = self.store.load()? {
+                state.restore(snapshot);
+            }
+            Ok(())
+        })
+    }
+
+    fn stop<'a>(&'a self, state: &'a Arc<AppState>) -> BoxFuture<'a, {{PROJECT_NAME}}_core::Result<()>> {
+        Box::pin(async move {
+            self.store.save(&state.snapshot()).map_err(|e| {
+                Error::Other(format!(
+                    "saving state to {} failed: {}",
+                    self.store.path().display(),
+                    e
+                ))
+            })
+        })
+    }
+}
+
+/// Plugins registered in the binary; stopped before the snapshot is saved
+struct Plugins;
+
+impl Service for Plugins {
+    fn name(&self) -> &'static str {
+        "plugins"
+    }
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &["snapshot"]
+    }
+
+    fn start<'a>(&'a self, _state: &'a Arc<AppState>) -> BoxFuture<'a, {{PROJECT_NAME}}_core::Result<()>> {
+        Box::pin(async {
+            tokio::task::spawn_blocking(core::init)
+                .await
+                .map_err(|e| Error::Other(e.to_string()))?
+        })
+    }
+
+    fn stop<'a>(&'a self, _state: &'a Arc<AppState>) -> BoxFuture<'a, {{PROJECT_NAME}}_core::Result<()>> {
+        Box::pin(async {
+            tokio::task::spawn_blocking(core::shutdown)
+                .await
+                .map_err(|e| Error::Other(e.to_string()))?
+        })
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
     let cli = Cli::parse();
-    
+    let error_format = cli.options.error_format;
+    match run(cli).await {
+        Ok(code) => ExitCode::from(code),
+        Err(err) => {
+            let report = ErrorReport::from_anyhow(&err);
+            eprintln!("{}", report.render(error_format).trim_end());
+            ExitCode::from(report.exit_code())
+        }
+    }
+}
+
+/// Run the command, returning its exit code on success
+async fn run(cli: Cli) -> Result<u8> {
+    let Cli { options, command } = cli;
+    let command = match command {
+        Commands::App(command) => command,
+        Commands::Standalone(command) => return run_standalone(command, &options),
+    };
+
     // Initialize utilities
     {{PROJECT_NAME}}_utils::init()?;
-    
+
     // Configure logging based on debug flag
-    if cli.debug {
+    if options.debug {
         logging::info("Debug mode enabled");
     }
-    
-    // Load configuration
-    let config = if let Some(config_path) = cli.config {
+
+    if let Some(config_path) = &options.config {
         logging::info(&format!("Loading config from: {}", config_path));
-        Config::default() // In real app, load from file
-    } else {
-        Config::default()
+    }
+    let loaded = config_loader(&options)?.load()?;
+    if let Some(profile) = &loaded.profile {
+        logging::info(&format!("Using profile: {}", profile));
+    }
+
+    // Services start concurrently where their dependencies allow
+    let events = EventBus::new();
+    if options.debug {
+        logging::log_events(&events);
+    }
+    let flags = feature_flags(&options, &loaded.config)?;
+    let state = Arc::new(AppState::with_events(loaded.config.clone(), events).with_flags(flags));
+    let store = SnapshotStore::for_config(&loaded.config)?;
+    // A run streams for as long as its input lasts, so apply edits of the file meanwhile
+    let _watcher = match (&command, &options.config) {
+        (AppCommand::Run { .. }, Some(_)) => Some(ConfigWatcher::spawn(
+            Arc::clone(&state),
+            config_loader(&options)?,
+            ConfigWatcher::DEFAULT_INTERVAL,
+        )?),
+        _ => None,
     };
-    
-    // Initialize core
-    core::init()?;
-    
-    // Handle commands
-    match cli.command {
-        Commands::Init { name } => {
+    let app = App::new(state)
+        .service(Snapshots { store })
+        .service(Plugins);
+    app.shutdown_coordinator().install_signal_handlers()?;
+    let shutdown = app.shutdown_coordinator().token();
+
+    // Handle the command as a task; failures are counted before the snapshot is saved
+    let finished = app
+        .run(move |state| async move {
+            let result = execute(command, Arc::clone(&state), loaded, shutdown).await;
+            if let Err(err) = &result {
+                state.metrics().record_error(err.as_ref());
+            }
+            result
+        })
+        .await?;
+    if let Some(e) = finished.stop_error {
+        logging::error(&format!("Stopping services failed: {}", e));
+    }
+
+    // Exit distinctly if interrupted, whether or not the command returned
+    match finished.output {
+        Some(output) if finished.shutdown.exit_code() == 0 => output,
+        _ => std::process::exit(finished.shutdown.exit_code()),
+    }
+}
+
+/// Configuration layers: defaults, file, environment, then CLI overrides
+fn config_loader(options: &GlobalOptions) -> Result<ConfigLoader> {
+    let mut loader = ConfigLoader::new().process_env();
+    if let Some(config_path) = &options.config {
+        loader = loader.file(config_path);
+    }
+    if let Some(profile) = &options.profile {
+        loader = loader.profile(profile);
+    }
+    for entry in &options.overrides {
+        let (key, value) = entry.split_once('=').ok_or_else(|| {
+            Error::Usage(format!("Invalid override (expected KEY=VALUE): {}", entry))
+        })?;
+        loader = loader.set(key, value);
+    }
+    if options.debug {
+        loader = loader.set("debug", "true");
+    }
+    Ok(loader)
+}
+
+/// Registered feature flags with environment and CLI overrides
+fn feature_flags(options: &GlobalOptions, config: &Config) -> Result<FeatureFlags> {
+    let mut overrides = FlagOverrides::new().process_env()?;
+    for name in &options.enable_flags {
+        overrides = overrides.enable(name);
+    }
+    for name in &options.disable_flags {
+        overrides = overrides.disable(name);
+    }
+    let flags = FeatureFlags::registered().with_overrides(overrides)?;
+    flags.check(config)?;
+    Ok(flags)
+}
+
+/// Run a command that needs no initialized application
+fn run_standalone(command: StandaloneCommand, options: &GlobalOptions) -> Result<u8> {
+    match command {
+        // The schema is static
+        StandaloneCommand::Config {
+            command: ConfigCommand::Schema,
+        } => println!("{:#}", config::json_schema()),
+        // Metrics come from the last snapshot
+        StandaloneCommand::Metrics { format } => {
+            let loaded = config_loader(options)?.load()?;
+            let state = AppState::new(loaded.config);
+            if let Some(snapshot) = SnapshotStore::for_config(&state.config())?.load()? {
+                state.restore(snapshot);
+            }
+            print!("{}", state.metrics().snapshot().render(format)?);
+        }
+        // Caches live outside the configuration
+        StandaloneCommand::Cache { command } => {
+            let dir = cache::default_dir()
+                .ok_or_else(|| Error::Config("no cache directory on this platform".to_string()))?;
+            match command {
+                CacheCommand::Stats => {
+                    let caches = cache::list(&dir)?;
+                    if caches.is_empty() {
+                        println!("No caches in {}", dir.display());
+                    }
+                    let width = caches.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+                    for (name, stats) in caches {
+                        println!("{name:<width$}  {stats}");
+                    }
+                }
+                CacheCommand::Clear => {
+                    let removed = cache::clear_all(&dir)?;
+                    println!("Removed {} caches from {}", removed.len(), dir.display());
+                }
+            }
+        }
+    }
+    Ok(0)
+}
+
+async fn execute(
+    command: AppCommand,
+    state: Arc<AppState>,
+    loaded: LoadedConfig,
+    shutdown: CancellationToken,
+) -> Result<u8> {
+    match command {
+        AppCommand::Init { name } => {
             let project_name = name.unwrap_or_else(|| "my_project".to_string());
             let capitalized = string::capitalize(&project_name);
             logging::info(&format!("Initializing project: {}", capitalized));
-            
+
             if !string::is_valid_identifier(&project_name) {
-                anyhow::bail!("Invalid project name: {}", project_name);
+                return Err(Error::Usage(format!("Invalid project name: {}", project_name)).into());
             }
-            
+
             println!("Project '{}' initialized successfully!", capitalized);
         }
-        Commands::Run { input } => {
+        AppCommand::Run {
+            input,
+            output,
+            format,
+        } => {
             logging::info("Running application");
-            if let Some(input_file) = input {
-                println!("Processing input file: {}", input_file);
-            } else {
-                println!("Running with default configuration");
+            let (format, input) = open_input(input.as_deref(), format.as_deref(), shutdown)?;
+            let records = Arc::new(Mutex::new(PipelineStats::default()));
+            let job = RunJob {
+                input: Some(input),
+                output: Some(open_output(output.as_deref())?),
+                output_format: format.output_format(),
+                state: Arc::clone(&state),
+                stats: Arc::clone(&records),
+            };
+            let queue = JobQueue::new(state)?;
+            let options = JobOptions {
+                max_attempts: 1,
+                ..JobOptions::default()
+            };
+            queue.submit(job, options);
+            // Workers are threads; wait for them without blocking the runtime
+            let stats = tokio::task::spawn_blocking(move || {
+                queue.wait_idle();
+                for dead in queue.take_dead_letters() {
+                    logging::error(&format!(
+                        "Job {} ({}) failed after {} attempt(s): {}",
+                        dead.id,
+                        dead.job.name(),
+                        dead.attempts,
+                        dead.error
+                    ));
+                }
+                queue.close()
+            })
+            .await?;
+            if stats.dead > 0 {
+                return Err(Error::Other(format!("{} job(s) failed", stats.dead)).into());
+            }
+            let records = *records.lock().unwrap_or_else(PoisonError::into_inner);
+            eprintln!("Records: {}", records);
+            if records.failed > 0 {
+                return Ok(ErrorCategory::Data.exit_code());
             }
-            println!("Application completed successfully!");
         }
-        Commands::Status => {
+        AppCommand::Status => {
             println!("{{PROJECT_NAME}} Status:");
             println!("  Version: {}", env!("CARGO_PKG_VERSION"));
-            println!("  Debug: {}", cli.debug);
-            println!("  Config: {:?}", config);
+            println!("  Debug: {}", loaded.config.debug);
+            println!(
+                "  Profile: {}",
+                loaded.profile.as_deref().unwrap_or("(none)")
+            );
+            println!("  Config:");
+            for (path, value, source) in loaded.fields() {
+                println!("    {} = {} ({})", path, value, source);
+            }
+            // Running operations live in the process running them; only history is saved
+            let history = state.operations().history();
+            println!("  History: {} operations finished", history.total());
+            for (name, count) in &history.counters {
+                println!("    {}: {}", name, count);
+            }
+            let health = state.check_health();
+            println!("  Health: {}", health.status());
+            for check in &health.checks {
+                println!(
+                    "    [{}] {}: {}",
+                    check.result.status, check.name, check.result.details
+                );
+            }
+            return Ok(health.exit_code());
+        }
+        AppCommand::Flags {
+            command: FlagsCommand::List,
+        } => {
+            let flags = state.flags();
+            if flags.is_empty() {
+                println!("No feature flags declared");
+            }
+            let width = flags.iter().map(|f| f.flag.name().len()).max().unwrap_or(0);
+            for state in flags {
+                println!(
+                    "{:<width$}  {:<4}  {} ({})",
+                    state.flag.name(),
+                    state.setting.to_string(),
+                    state.flag.description(),
+                    state.source
+                );
+            }
         }
     }
-    
-    Ok(())
-}
\ No newline at end of file
+
+    Ok(0)
+}
@@ -0,0 +1,217 @@
+//! End-to-end tests of the `run` command
+
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+/// Run the binary with `config` and state kept in `dir`, feeding `stdin` to it
+fn run_with_config(dir: &Path, config: &str, args: &[&str], stdin: &str) -> Output {
+    let path = dir.join("config.toml");
+    let data_dir = format!("data_dir = {:?}\n", dir.join("data"));
+    std::fs::write(&path, data_dir + config).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_{{PROJECT_NAME}}"))
+        .arg("--config")
+        .arg(&path)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn run(dir: &Path, args: &[&str], stdin: &str) -> Output {
+    run_with_config(dir, "", args, stdin)
+}
+
+#[test]
+fn test_plain_lines_pass_through() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = run(dir.path(), &["run"], "a\nb, c\n\n{not json\n");
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "a\nb, c\n{not json\n"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Records: 3 processed, 0 failed"));
+
+    let notes = dir.path().join("notes.txt");
+    std::fs::write(&notes, "first\nsecond\n").unwrap();
+    let output = run(dir.path(), &["run", "-i", notes.to_str().unwrap()], "");
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "first\nsecond\n");
+}
+
+#[test]
+fn test_structured_input_is_written_as_json_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = run(dir.path(), &["run"], "name,count\nx,1\ny,2\n");
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "{\"count\":\"1\",\"name\":\"x\"}\n{\"count\":\"2\",\"name\":\"y\"}\n"
+    );
+}
+
+#[test]
+fn test_failed_records_are_counted_in_metrics() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = "{\"a\":1}\n{bad\n{\"b\":2}\n";
+    let output = run(dir.path(), &["run", "--format", "ndjson"], input);
+    assert_eq!(output.status.code(), Some(65), "{output:?}");
+
+    let output = run(dir.path(), &["metrics"], "");
+    assert!(output.status.success(), "{output:?}");
+    let metrics = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        metrics.contains("_errors_total{code=\"E0009\",variant=\"Parse\"} 1\n"),
+        "{metrics}"
+    );
+}
+
+#[test]
+fn test_rate_limit_paces_records() {
+    let dir = tempfile::tempdir().unwrap();
+    // One record at once, then one every 200 ms
+    let config = "[rate_limits.run]\nrequests = 5\nperiod_secs = 1\nburst = 1\n";
+    let started = Instant::now();
+    let output = run_with_config(dir.path(), config, &["run"], "a\nb\nc\nd\n");
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a\nb\nc\nd\n");
+    assert!(started.elapsed() >= Duration::from_millis(550));
+}
+
+#[test]
+fn test_config_edits_apply_during_run() {
+    use std::io::{BufRead, BufReader};
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("config.toml");
+    let data_dir = format!("data_dir = {:?}\n", dir.path().join("data"));
+    std::fs::write(&config, &data_dir).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_{{PROJECT_NAME}}"))
+        .arg("--config")
+        .arg(&config)
+        .args(["--debug", "run"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let stdin = child.stdin.take();
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    while !line.contains("Running application") {
+        line.clear();
+        assert_ne!(stderr.read_line(&mut line).unwrap(), 0, "exited early");
+    }
+
+    std::fs::write(&config, data_dir + "max_concurrent = 8\n").unwrap();
+    while !line.contains("configuration loaded (1 changed)") {
+        line.clear();
+        assert_ne!(stderr.read_line(&mut line).unwrap(), 0, "exited early");
+    }
+    drop(stdin);
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn test_rate_limit_edits_apply_during_run() {
+    use std::io::{BufRead, BufReader};
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("config.toml");
+    let data_dir = format!("data_dir = {:?}\n", dir.path().join("data"));
+    let hourly = "[rate_limits.run]\nrequests = 1\nperiod_secs = 3600\nburst = 1\n";
+    std::fs::write(&config, data_dir.clone() + hourly).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_{{PROJECT_NAME}}"))
+        .arg("--config")
+        .arg(&config)
+        .args(["--debug", "run", "--format", "text"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    while !line.contains("Running application") {
+        line.clear();
+        assert_ne!(stderr.read_line(&mut line).unwrap(), 0, "exited early");
+    }
+    stdin.write_all(b"a\n").unwrap();
+
+    // Without the hourly limit the remaining records must not wait for a permit
+    std::fs::write(&config, data_dir).unwrap();
+    while !line.contains("configuration loaded (") {
+        line.clear();
+        assert_ne!(stderr.read_line(&mut line).unwrap(), 0, "exited early");
+    }
+    stdin.write_all(b"b\nc\n").unwrap();
+    drop(stdin);
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            panic!("records still wait for the old limit");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    assert!(status.success());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_interrupt_stops_waiting_for_stdin() {
+    use std::io::{BufRead, BufReader, Read};
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("config.toml");
+    std::fs::write(
+        &config,
+        format!("data_dir = {:?}\n", dir.path().join("data")),
+    )
+    .unwrap();
+    // Stdin stays open, so only the interrupt can end the run
+    let mut child = Command::new(env!("CARGO_BIN_EXE_{{PROJECT_NAME}}"))
+        .arg("--config")
+        .arg(&config)
+        .arg("run")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let _stdin = child.stdin.take();
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    while !line.contains("Running application") {
+        line.clear();
+        assert_ne!(stderr.read_line(&mut line).unwrap(), 0, "exited early");
+    }
+
+    let started = Instant::now();
+    let status = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let status = child.wait().unwrap();
+    let mut rest = String::new();
+    stderr.read_to_string(&mut rest).unwrap();
+    assert_eq!(status.code(), Some(130), "{rest}");
+    assert!(rest.contains("press Ctrl-C again"), "{rest}");
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
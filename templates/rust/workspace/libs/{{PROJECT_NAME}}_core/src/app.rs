@@ -0,0 +1,521 @@
+//! Async application lifecycle
+//!
+//! An [`App`] owns the shared [`AppState`] and a set of [`Service`]s.
+//! [`App::run`] starts the services in waves, each wave concurrently once its
+//! dependencies are up, runs a command handler as a tokio task until it
+//! returns or shutdown is requested, drains running operations through the
+//! [`ShutdownCoordinator`] and stops the services in reverse dependency
+//! order. [`App::run_blocking`] does the same on a runtime of its own, for
+//! tools that are not async.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::future::Future;
+use std::panic;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::shutdown::{ShutdownCoordinator, ShutdownOutcome};
+use crate::types::AppState;
+use crate::{Error, Result};
+
+/// Interval between checks for a shutdown request while the handler runs
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Boxed future returned by [`Service`] methods
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Long-lived component started before and stopped after the command handler
+pub trait Service: Send + Sync {
+    /// Unique service name
+    fn name(&self) -> &'static str;
+
+    /// Names of services that must be started before and stopped after this one
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Called once, after all dependencies have started
+    fn start<'a>(&'a self, state: &'a Arc<AppState>) -> BoxFuture<'a, Result<()>>;
+
+    /// Called once, before any dependency is stopped
+    fn stop<'a>(&'a self, _state: &'a Arc<AppState>) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Result of [`App::run`]
+#[derive(Debug)]
+pub struct Finished<T> {
+    /// Value returned by the command handler, or `None` if it did not return
+    /// within the grace period after shutdown was requested
+    pub output: Option<T>,
+    /// How draining the remaining operations ended
+    pub shutdown: ShutdownOutcome,
+    /// First failure while stopping services; every service is stopped regardless
+    pub stop_error: Option<Error>,
+}
+
+/// Services and state of a running application
+pub struct App {
+    state: Arc<AppState>,
+    services: Vec<Arc<dyn Service>>,
+    shutdown: ShutdownCoordinator,
+}
+
+impl fmt::Debug for App {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("App")
+            .field(
+                "services",
+                &self.services.iter().map(|s| s.name()).collect::<Vec<_>>(),
+            )
+            .field("shutdown", &self.shutdown)
+            .finish()
+    }
+}
+
+impl App {
+    /// Create an application around `state`, draining with its configured grace period
+    pub fn new(state: Arc<AppState>) -> Self {
+        let shutdown = ShutdownCoordinator::new(
+            state.operations().clone(),
+            state.config().shutdown_grace_period(),
+        );
+        Self {
+            state,
+            services: Vec::new(),
+            shutdown,
+        }
+    }
+
+    /// Add a service
+    pub fn service(mut self, service: impl Service + 'static) -> Self {
+        self.services.push(Arc::new(service));
+        self
+    }
+
+    /// Shared application state
+    pub fn state(&self) -> &Arc<AppState> {
+        &self.state
+    }
+
+    /// Coordinator used to drain operations after the handler returns
+    ///
+    /// Install its signal handlers to turn Ctrl-C into a graceful shutdown.
+    pub fn shutdown_coordinator(&self) -> &ShutdownCoordinator {
+        &self.shutdown
+    }
+
+    /// Start the services, run `handler` as a task, then shut everything down
+    ///
+    /// If a service fails to start, the services already started are stopped
+    /// again and `handler` is not run. Once shutdown is requested, `handler`
+    /// and then the running operations share one grace period; `handler` is
+    /// aborted if it has not returned by the end of it. A panic in
+    /// `handler` is resumed after the services have been stopped.
+    pub async fn run<F, Fut, T>(self, handler: F) -> Result<Finished<T>>
+    where
+        F: FnOnce(Arc<AppState>) -> Fut,
+        Fut: Future<Output = T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let waves = waves(&self.services)?;
+        start(&waves, &self.state).await?;
+
+        let mut task = tokio::spawn(handler(Arc::clone(&self.state)));
+        let (output, deadline) = tokio::select! {
+            output = &mut task => (Some(output), None),
+            () = requested(&self.shutdown) => {
+                // Operations are cancelled, so a cooperative handler returns soon
+                let grace = self.state.config().shutdown_grace_period();
+                let deadline = Instant::now().checked_add(grace);
+                let output = match deadline {
+                    Some(deadline) => tokio::time::timeout_at(deadline.into(), &mut task).await,
+                    None => Ok((&mut task).await),
+                };
+                if output.is_err() {
+                    task.abort();
+                }
+                (output.ok(), Some(deadline))
+            }
+        };
+
+        // Draining only gets what is left of the grace period the handler had
+        let coordinator = self.shutdown.clone();
+        let shutdown = tokio::task::spawn_blocking(move || match deadline {
+            Some(deadline) => coordinator.shutdown_until(deadline),
+            None => coordinator.shutdown(),
+        })
+        .await
+        .unwrap_or_else(|e| panic::resume_unwind(e.into_panic()));
+        let stop_error = stop(&waves, &self.state).await.err();
+
+        let output = match output {
+            Some(Ok(output)) => Some(output),
+            Some(Err(e)) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+            Some(Err(e)) => return Err(Error::Service(format!("command handler failed: {e}"))),
+            None => None,
+        };
+        Ok(Finished {
+            output,
+            shutdown,
+            stop_error,
+        })
+    }
+
+    /// Synchronous [`App::run`] on a new multi-threaded runtime
+    pub fn run_blocking<F, Fut, T>(self, handler: F) -> Result<Finished<T>>
+    where
+        F: FnOnce(Arc<AppState>) -> Fut,
+        Fut: Future<Output = T> + Send + 'static,
+        T: Send + 'static,
+    {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?
+            .block_on(self.run(handler))
+    }
+}
+
+/// Resolve once shutdown has been requested through `shutdown`
+async fn requested(shutdown: &ShutdownCoordinator) {
+    while !shutdown.is_shutdown_requested() {
+        tokio::time::sleep(SHUTDOWN_POLL_INTERVAL).await;
+    }
+}
+
+type Wave = Vec<Arc<dyn Service>>;
+
+/// Group services into waves that only depend on earlier waves
+///
+/// Services within a wave are ordered by name so the order is deterministic.
+fn waves(services: &[Arc<dyn Service>]) -> Result<Vec<Wave>> {
+    let mut by_name = BTreeMap::new();
+    for service in services {
+        if by_name
+            .insert(service.name(), Arc::clone(service))
+            .is_some()
+        {
+            return Err(Error::Service(format!(
+                "service {} is registered more than once",
+                service.name()
+            )));
+        }
+    }
+    for service in by_name.values() {
+        if let Some(missing) = service
+            .dependencies()
+            .iter()
+            .find(|dep| !by_name.contains_key(*dep))
+        {
+            return Err(Error::Service(format!(
+                "service {} depends on unknown service {missing}",
+                service.name()
+            )));
+        }
+    }
+
+    let mut waves = Vec::new();
+    let mut done = BTreeSet::new();
+    while done.len() < by_name.len() {
+        let wave: Wave = by_name
+            .values()
+            .filter(|service| !done.contains(service.name()))
+            .filter(|service| service.dependencies().iter().all(|dep| done.contains(dep)))
+            .cloned()
+            .collect();
+        if wave.is_empty() {
+            let cycle: Vec<_> = by_name
+                .keys()
+                .filter(|name| !done.contains(*name))
+                .copied()
+                .collect();
+            return Err(Error::Service(format!(
+                "dependency cycle between services: {}",
+                cycle.join(", ")
+            )));
+        }
+        done.extend(wave.iter().map(|service| service.name()));
+        waves.push(wave);
+    }
+    Ok(waves)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Phase {
+    Start,
+    Stop,
+}
+
+/// Start or stop every service of `wave` concurrently
+async fn run_wave(
+    wave: &[Arc<dyn Service>],
+    state: &Arc<AppState>,
+    phase: Phase,
+) -> Vec<(Arc<dyn Service>, Result<()>)> {
+    let tasks: Vec<_> = wave
+        .iter()
+        .map(|service| {
+            let task = {
+                let service = Arc::clone(service);
+                let state = Arc::clone(state);
+                tokio::spawn(async move {
+                    match phase {
+                        Phase::Start => service.start(&state).await,
+                        Phase::Stop => service.stop(&state).await,
+                    }
+                })
+            };
+            (Arc::clone(service), task)
+        })
+        .collect();
+
+    let mut results = Vec::with_capacity(tasks.len());
+    for (service, task) in tasks {
+        let result = task
+            .await
+            .unwrap_or_else(|e| Err(Error::Service(format!("task failed: {e}"))));
+        results.push((service, result));
+    }
+    results
+}
+
+/// Start `waves` in order; on failure, stop whatever has started
+async fn start(waves: &[Wave], state: &Arc<AppState>) -> Result<()> {
+    for (index, wave) in waves.iter().enumerate() {
+        let mut started = Vec::new();
+        let mut failure = None;
+        for (service, result) in run_wave(wave, state, Phase::Start).await {
+            match result {
+                Ok(()) => {
+                    tracing::debug!(service = service.name(), "service started");
+                    started.push(service);
+                }
+                Err(e) if failure.is_none() => {
+                    failure = Some(Error::ServiceFailed {
+                        service: service.name(),
+                        action: "start",
+                        source: Box::new(e),
+                    });
+                }
+                Err(_) => {}
+            }
+        }
+        if let Some(failure) = failure {
+            let mut running = waves[..index].to_vec();
+            running.push(started);
+            let _ = stop(&running, state).await;
+            return Err(failure);
+        }
+    }
+    Ok(())
+}
+
+/// Stop `waves` in reverse order, reporting the first failure
+async fn stop(waves: &[Wave], state: &Arc<AppState>) -> Result<()> {
+    let mut first = Ok(());
+    for wave in waves.iter().rev() {
+        for (service, result) in run_wave(wave, state, Phase::Stop).await {
+            if let Err(e) = result {
+                tracing::error!(service = service.name(), error = %e, "service stop failed");
+                if first.is_ok() {
+                    first = Err(Error::ServiceFailed {
+                        service: service.name(),
+                        action: "stop",
+                        source: Box::new(e),
+                    });
+                }
+            }
+        }
+    }
+    first
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Config;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    struct TestService {
+        name: &'static str,
+        dependencies: &'static [&'static str],
+        fail: bool,
+        log: Log,
+    }
+
+    impl TestService {
+        fn new(name: &'static str, dependencies: &'static [&'static str], log: &Log) -> Self {
+            Self {
+                name,
+                dependencies,
+                fail: false,
+                log: Arc::clone(log),
+            }
+        }
+    }
+
+    impl Service for TestService {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn dependencies(&self) -> &'static [&'static str] {
+            self.dependencies
+        }
+
+        fn start<'a>(&'a self, _state: &'a Arc<AppState>) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                if self.fail {
+                    return Err(Error::Io(std::io::Error::other("boom")));
+                }
+                self.log
+                    .lock()
+                    .unwrap()
+                    .push(format!("start {}", self.name));
+                Ok(())
+            })
+        }
+
+        fn stop<'a>(&'a self, _state: &'a Arc<AppState>) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async move {
+                self.log.lock().unwrap().push(format!("stop {}", self.name));
+                Ok(())
+            })
+        }
+    }
+
+    fn app() -> App {
+        App::new(Arc::new(AppState::new(Config::default())))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_services_start_and_stop_in_dependency_order() {
+        let log = Log::default();
+        let app = app()
+            .service(TestService::new("http", &["db", "cache"], &log))
+            .service(TestService::new("db", &[], &log))
+            .service(TestService::new("cache", &[], &log));
+
+        let handler_log = Arc::clone(&log);
+        let finished = app
+            .run(move |_state| async move {
+                handler_log.lock().unwrap().push("handler".to_string());
+                7
+            })
+            .await
+            .unwrap();
+        assert_eq!(finished.output, Some(7));
+        assert_eq!(finished.shutdown, ShutdownOutcome::Completed);
+        assert!(finished.stop_error.is_none());
+
+        let log = log.lock().unwrap();
+        // `cache` and `db` start concurrently, so only compare the waves
+        let mut first_wave = log[..2].to_vec();
+        first_wave.sort();
+        assert_eq!(first_wave, ["start cache", "start db"]);
+        assert_eq!(log[2..4], ["start http", "handler"]);
+        assert_eq!(log[4], "stop http");
+        assert_eq!(log.len(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_start_failure_stops_started_services() {
+        let log = Log::default();
+        let failing = TestService {
+            fail: true,
+            ..TestService::new("http", &["db"], &log)
+        };
+        let result = app()
+            .service(TestService::new("db", &[], &log))
+            .service(failing)
+            .run(|_state| async { unreachable!("handler must not run") })
+            .await;
+
+        let Err(error @ Error::ServiceFailed { service, .. }) = result else {
+            panic!("expected service error");
+        };
+        assert_eq!(service, "http");
+        // Classified like the cause, not as an internal error
+        assert_eq!(error.exit_code(), 74);
+        assert_eq!(*log.lock().unwrap(), ["start db", "stop db"]);
+
+        let cycle = app()
+            .service(TestService::new("a", &["b"], &log))
+            .service(TestService::new("b", &["a"], &log))
+            .run(|_state| async {})
+            .await;
+        assert!(matches!(cycle, Err(Error::Service(_))));
+    }
+
+    #[test]
+    fn test_run_blocking() {
+        let finished = app()
+            .run_blocking(|state| async move { state.config().max_concurrent })
+            .unwrap();
+        assert_eq!(finished.output, Some(4));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_request_ends_handler() {
+        let app = app();
+        let shutdown = app.shutdown_coordinator().clone();
+        let finished = app
+            .run(move |state| async move {
+                let operation = state.start_operation("work");
+                shutdown.request_shutdown();
+                while !operation.is_cancelled() {
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+                "stopped"
+            })
+            .await
+            .unwrap();
+        assert_eq!(finished.output, Some("stopped"));
+        assert_eq!(finished.shutdown, ShutdownOutcome::Interrupted);
+
+        let app = App::new(Arc::new(AppState::new(Config {
+            shutdown_grace_period_secs: 0,
+            ..Config::default()
+        })));
+        let shutdown = app.shutdown_coordinator().clone();
+        let finished = app
+            .run(move |_state| async move {
+                shutdown.request_shutdown();
+                std::future::pending::<()>().await
+            })
+            .await
+            .unwrap();
+        assert_eq!(finished.output, None);
+    }
+
+    #[tokio::test]
+    async fn test_handler_and_operations_share_the_grace_period() {
+        let app = App::new(Arc::new(AppState::new(Config {
+            shutdown_grace_period_secs: 1,
+            ..Config::default()
+        })));
+        let _stuck = app.state().start_operation("stuck");
+        let shutdown = app.shutdown_coordinator().clone();
+        let started = Instant::now();
+        let finished = app
+            .run(move |_state| async move {
+                shutdown.request_shutdown();
+                std::future::pending::<()>().await
+            })
+            .await
+            .unwrap();
+        assert_eq!(finished.output, None);
+        assert_eq!(
+            finished.shutdown,
+            ShutdownOutcome::GraceExpired { remaining: 1 }
+        );
+        assert!(started.elapsed() < Duration::from_millis(1500));
+    }
+}
@@ -0,0 +1,620 @@
+//! Layered configuration loading
+//!
+//! Values are merged from, in increasing order of precedence: built-in
+//! defaults, a TOML or JSON file, the selected profile of that file,
+//! [`env_prefix`]ed environment variables and command-line overrides. The
+//! layer that set each field is recorded so callers can report where a value
+//! came from.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+
+use crate::types::{Config, Secret};
+use crate::{Error, Result};
+
+/// Separator between path segments in environment variable names
+const ENV_PATH_SEPARATOR: &str = "__";
+
+/// Table of a configuration file holding named profiles
+const PROFILES_KEY: &str = "profiles";
+
+/// Prefix of environment variables read by the loader: the project name in upper case and `_`
+pub fn env_prefix() -> String {
+    format!("{}_", "{{PROJECT_NAME}}".to_uppercase())
+}
+
+/// Environment variable selecting the profile: [`env_prefix`] followed by `PROFILE`
+pub fn profile_env_var() -> String {
+    format!("{}PROFILE", env_prefix())
+}
+
+/// Configuration layer that set a value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// Built-in default
+    Default,
+    /// Configuration file
+    File(PathBuf),
+    /// Profile with the given name in the configuration file
+    Profile(String),
+    /// Environment variable with the given name
+    Env(String),
+    /// Command-line override
+    Cli,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => write!(f, "default"),
+            Self::File(path) => write!(f, "file {}", path.display()),
+            Self::Profile(name) => write!(f, "profile {name}"),
+            Self::Env(name) => write!(f, "env {name}"),
+            Self::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+/// Record of the layer that set each configuration field
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Provenance {
+    sources: BTreeMap<String, Source>,
+}
+
+impl Provenance {
+    /// Source of the field at the given dotted path
+    pub fn get(&self, path: &str) -> &Source {
+        self.sources.get(path).unwrap_or(&Source::Default)
+    }
+
+    /// Iterate over all recorded fields and their sources
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Source)> {
+        self.sources
+            .iter()
+            .map(|(path, source)| (path.as_str(), source))
+    }
+
+    fn record(&mut self, path: String, source: Source) {
+        self.sources.insert(path, source);
+    }
+}
+
+/// Configuration together with the provenance of its fields
+#[derive(Debug, Clone)]
+pub struct LoadedConfig {
+    /// Merged configuration
+    pub config: Config,
+    /// Layer that set each field
+    pub provenance: Provenance,
+    /// Name of the applied profile, if any
+    pub profile: Option<String>,
+}
+
+impl LoadedConfig {
+    /// Every configuration field with its current value and source
+    pub fn fields(&self) -> Vec<(String, Value, Source)> {
+        let mut leaves = Vec::new();
+        if let Ok(value) = serde_json::to_value(&self.config) {
+            collect_leaves(&value, String::new(), &mut leaves);
+        }
+        leaves
+            .into_iter()
+            .map(|(path, value)| {
+                let source = self.provenance.get(&path).clone();
+                (path, value, source)
+            })
+            .collect()
+    }
+}
+
+/// Builder that merges configuration layers into a [`Config`]
+#[derive(Debug, Clone, Default)]
+pub struct ConfigLoader {
+    file: Option<PathBuf>,
+    profile: Option<String>,
+    env: Vec<(String, String)>,
+    overrides: Vec<(String, String)>,
+}
+
+impl ConfigLoader {
+    /// Create a loader that only applies defaults
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read a TOML or JSON configuration file
+    pub fn file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.file = Some(path.into());
+        self
+    }
+
+    /// Path of the configuration file, if any
+    pub fn file_path(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    /// Apply the named profile of the configuration file over its base settings
+    ///
+    /// Takes precedence over the profile named by [`profile_env_var`].
+    pub fn profile(mut self, name: impl Into<String>) -> Self {
+        self.profile = Some(name.into());
+        self
+    }
+
+    /// Use the given environment variables
+    pub fn env<I, K, V>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.env = vars
+            .into_iter()
+            .map(|(key, value)| (key.into(), value.into()))
+            .collect();
+        self
+    }
+
+    /// Use the environment of the current process
+    pub fn process_env(self) -> Self {
+        self.env(std::env::vars())
+    }
+
+    /// Override a field from the command line by dotted path
+    pub fn set(mut self, path: impl Into<String>, value: impl Into<String>) -> Self {
+        self.overrides.push((path.into(), value.into()));
+        self
+    }
+
+    /// Merge all layers, deserialize the result and validate it
+    pub fn load(&self) -> Result<LoadedConfig> {
+        let mut merged = serde_json::to_value(Config::default())
+            .map_err(|e| Error::Config(format!("cannot serialize defaults: {e}")))?;
+        let mut provenance = Provenance::default();
+
+        let mut profiles = Map::new();
+        if let Some(path) = &self.file {
+            let mut layer = read_file(path)?;
+            if let Some(table) = layer
+                .as_object_mut()
+                .and_then(|map| map.remove(PROFILES_KEY))
+            {
+                let Value::Object(table) = table else {
+                    return Err(Error::Config(format!(
+                        "`{PROFILES_KEY}` in {} must be a table",
+                        path.display()
+                    )));
+                };
+                profiles = table;
+            }
+            let mut leaves = Vec::new();
+            collect_leaves(&layer, String::new(), &mut leaves);
+            merge(&mut merged, layer);
+            for (field, _) in leaves {
+                provenance.record(field, Source::File(path.clone()));
+            }
+        }
+
+        let profile = self.selected_profile();
+        if let Some(name) = &profile {
+            let layer = profiles.remove(name).ok_or_else(|| {
+                let valid: Vec<&str> = profiles.keys().map(String::as_str).collect();
+                let valid = if valid.is_empty() {
+                    "none defined".to_string()
+                } else {
+                    valid.join(", ")
+                };
+                Error::Config(format!(
+                    "unknown profile {name:?} (valid profiles: {valid})"
+                ))
+            })?;
+            let mut leaves = Vec::new();
+            collect_leaves(&layer, String::new(), &mut leaves);
+            merge(&mut merged, layer);
+            for (field, _) in leaves {
+                provenance.record(field, Source::Profile(name.clone()));
+            }
+        }
+
+        let prefix = env_prefix();
+        let mut defaults = Vec::new();
+        collect_leaves(&merged, String::new(), &mut defaults);
+        for (path, current) in defaults {
+            let name = format!(
+                "{prefix}{}",
+                path.replace('.', ENV_PATH_SEPARATOR).to_uppercase()
+            );
+            if let Some((_, raw)) = self.env.iter().rev().find(|(key, _)| *key == name) {
+                let value = coerce(raw, &current)
+                    .map_err(|e| Error::Config(format!("invalid value for {name}: {e}")))?;
+                set_path(&mut merged, &path, value)?;
+                provenance.record(path, Source::Env(name));
+            }
+        }
+
+        for (path, raw) in &self.overrides {
+            let current = get_path(&merged, path)
+                .ok_or_else(|| Error::Config(format!("unknown configuration key: {path}")))?;
+            let value = coerce(raw, current)
+                .map_err(|e| Error::Config(format!("invalid value for {path}: {e}")))?;
+            set_path(&mut merged, path, value)?;
+            provenance.record(path.clone(), Source::Cli);
+        }
+
+        let config: Config = serde_json::from_value(merged)
+            .map_err(|e| Error::Config(format!("invalid configuration: {e}")))?;
+        config.validate()?;
+        Ok(LoadedConfig {
+            config,
+            provenance,
+            profile,
+        })
+    }
+
+    /// Profile set explicitly, or else named by the environment
+    fn selected_profile(&self) -> Option<String> {
+        let name = profile_env_var();
+        self.profile.clone().or_else(|| {
+            self.env
+                .iter()
+                .rev()
+                .find(|(key, value)| *key == name && !value.is_empty())
+                .map(|(_, value)| value.clone())
+        })
+    }
+}
+
+/// JSON Schema describing [`Config`], including doc comments, defaults and constraints
+#[cfg(feature = "schema")]
+pub fn json_schema() -> Value {
+    let mut schema = serde_json::to_value(schemars::schema_for!(Config)).unwrap_or(Value::Null);
+    if let Some(properties) = schema.get("properties").cloned() {
+        schema["properties"][PROFILES_KEY] = serde_json::json!({
+            "description": "Named profiles whose settings override the base settings when selected",
+            "type": "object",
+            "additionalProperties": {
+                "type": "object",
+                "properties": properties,
+                "additionalProperties": false,
+            },
+        });
+    }
+    schema
+}
+
+/// A single field that differs between two configurations
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    /// Dotted path of the field
+    pub path: String,
+    /// Previous value, or null if the field was absent
+    pub old: Value,
+    /// New value, or null if the field was removed
+    pub new: Value,
+}
+
+/// Secret fields by path; they serialize as [`REDACTED`](crate::types::REDACTED),
+/// so [`diff`] compares them by value
+fn secrets(config: &Config) -> [(&'static str, Option<&Secret>); 1] {
+    [("api_token", config.api_token.as_ref())]
+}
+
+/// List the fields that differ between `old` and `new`
+///
+/// A changed secret is listed with its redacted values.
+pub fn diff(old: &Config, new: &Config) -> Vec<FieldChange> {
+    let mut before = Vec::new();
+    let mut after = Vec::new();
+    if let (Ok(old), Ok(new)) = (serde_json::to_value(old), serde_json::to_value(new)) {
+        collect_leaves(&old, String::new(), &mut before);
+        collect_leaves(&new, String::new(), &mut after);
+    }
+    let before: BTreeMap<_, _> = before.into_iter().collect();
+    let after: BTreeMap<_, _> = after.into_iter().collect();
+    let rotated: Vec<&str> = secrets(old)
+        .into_iter()
+        .zip(secrets(new))
+        .filter(|((_, old), (_, new))| old != new)
+        .map(|((path, _), _)| path)
+        .collect();
+
+    let mut paths: Vec<&String> = before.keys().chain(after.keys()).collect();
+    paths.sort();
+    paths.dedup();
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let old = before.get(path).cloned().unwrap_or(Value::Null);
+            let new = after.get(path).cloned().unwrap_or(Value::Null);
+            (old != new || rotated.contains(&path.as_str())).then(|| FieldChange {
+                path: path.clone(),
+                old,
+                new,
+            })
+        })
+        .collect()
+}
+
+/// Parse a configuration file into a JSON value based on its extension
+fn read_file(path: &Path) -> Result<Value> {
+    let contents = std::fs::read_to_string(path)?;
+    let display = path.display();
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(&contents)
+            .map_err(|e| Error::Config(format!("cannot parse {display}: {e}"))),
+        Some("json") => serde_json::from_str(&contents)
+            .map_err(|e| Error::Config(format!("cannot parse {display}: {e}"))),
+        _ => Err(Error::Config(format!(
+            "unsupported config format for {display}: expected .toml or .json"
+        ))),
+    }
+}
+
+/// Recursively overlay `layer` onto `base`
+fn merge(base: &mut Value, layer: Value) {
+    match (base, layer) {
+        (Value::Object(base), Value::Object(layer)) => {
+            for (key, value) in layer {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+/// Collect all non-object values with their dotted paths
+fn collect_leaves(value: &Value, prefix: String, out: &mut Vec<(String, Value)>) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, child) in map {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{prefix}.{key}")
+                };
+                collect_leaves(child, path, out);
+            }
+        }
+        _ if !prefix.is_empty() => out.push((prefix, value.clone())),
+        _ => {}
+    }
+}
+
+fn get_path<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(value, |node, key| node.get(key))
+}
+
+fn set_path(value: &mut Value, path: &str, new: Value) -> Result<()> {
+    let mut node = value;
+    for key in path.split('.') {
+        if !node.is_object() {
+            *node = Value::Object(Map::new());
+        }
+        node = match node {
+            Value::Object(map) => map.entry(key).or_insert(Value::Null),
+            _ => return Err(Error::Config(format!("cannot set {path}"))),
+        };
+    }
+    *node = new;
+    Ok(())
+}
+
+/// Convert a raw string into a value shaped like `current`
+fn coerce(raw: &str, current: &Value) -> std::result::Result<Value, String> {
+    match current {
+        Value::String(_) => Ok(Value::String(raw.to_string())),
+        Value::Bool(_) => match raw.to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(Value::Bool(true)),
+            "0" | "false" | "no" | "off" => Ok(Value::Bool(false)),
+            _ => Err(format!("expected a boolean, got {raw:?}")),
+        },
+        Value::Number(_) => serde_json::from_str::<serde_json::Number>(raw)
+            .map(Value::Number)
+            .map_err(|_| format!("expected a number, got {raw:?}")),
+        _ => Ok(serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn write_config(extension: &str, contents: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::Builder::new()
+            .suffix(extension)
+            .tempfile()
+            .unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_defaults_only() {
+        let loaded = ConfigLoader::new().load().unwrap();
+        assert_eq!(loaded.config, Config::default());
+        assert_eq!(loaded.provenance.get("name"), &Source::Default);
+    }
+
+    #[test]
+    fn test_layer_precedence() {
+        let file = write_config(".toml", "name = \"from_file\"\nmax_concurrent = 8\n");
+        let loaded = ConfigLoader::new()
+            .file(file.path())
+            .env([(format!("{}MAX_CONCURRENT", env_prefix()), "16")])
+            .set("debug", "true")
+            .load()
+            .unwrap();
+
+        assert_eq!(loaded.config.name, "from_file");
+        assert_eq!(loaded.config.max_concurrent, 16);
+        assert!(loaded.config.debug);
+        assert_eq!(
+            loaded.provenance.get("name"),
+            &Source::File(file.path().to_path_buf())
+        );
+        assert_eq!(
+            loaded.provenance.get("max_concurrent"),
+            &Source::Env(format!("{}MAX_CONCURRENT", env_prefix()))
+        );
+        assert_eq!(loaded.provenance.get("debug"), &Source::Cli);
+    }
+
+    #[test]
+    fn test_profiles_override_base() {
+        let file = write_config(
+            ".toml",
+            "max_concurrent = 2\n\n[profiles.dev]\ndebug = true\n\n[profiles.prod]\nmax_concurrent = 32\n",
+        );
+        let base = ConfigLoader::new().file(file.path()).load().unwrap();
+        assert_eq!(base.profile, None);
+        assert_eq!(base.config.max_concurrent, 2);
+        assert!(!base.config.debug);
+
+        let prod = ConfigLoader::new()
+            .file(file.path())
+            .env([(profile_env_var(), "dev")])
+            .profile("prod")
+            .load()
+            .unwrap();
+        assert_eq!(prod.profile.as_deref(), Some("prod"));
+        assert_eq!(prod.config.max_concurrent, 32);
+        assert!(!prod.config.debug);
+        assert_eq!(
+            prod.provenance.get("max_concurrent"),
+            &Source::Profile("prod".to_string())
+        );
+
+        let dev = ConfigLoader::new()
+            .file(file.path())
+            .env([(profile_env_var(), "dev")])
+            .load()
+            .unwrap();
+        assert!(dev.config.debug);
+        assert_eq!(dev.config.max_concurrent, 2);
+
+        let Err(Error::Config(message)) = ConfigLoader::new()
+            .file(file.path())
+            .profile("staging")
+            .load()
+        else {
+            panic!("expected unknown profile error");
+        };
+        assert!(message.contains("\"staging\""));
+        assert!(message.contains("dev, prod"));
+    }
+
+    #[test]
+    fn test_json_file() {
+        let file = write_config(".json", r#"{"debug": true}"#);
+        let loaded = ConfigLoader::new().file(file.path()).load().unwrap();
+        assert!(loaded.config.debug);
+    }
+
+    #[test]
+    fn test_rejects_unknown_keys_and_bad_values() {
+        let file = write_config(".toml", "unknown = 1\n");
+        assert!(ConfigLoader::new().file(file.path()).load().is_err());
+        assert!(ConfigLoader::new().set("unknown", "1").load().is_err());
+        assert!(ConfigLoader::new().set("debug", "maybe").load().is_err());
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = Config::default();
+        let new = Config {
+            max_concurrent: 8,
+            ..Config::default()
+        };
+        let changes = diff(&old, &new);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].path, "max_concurrent");
+        assert_eq!(changes[0].new, Value::from(8));
+        assert!(diff(&old, &old).is_empty());
+
+        let token = |value: &str| Config {
+            api_token: Some(Secret::new(value)),
+            ..Config::default()
+        };
+        let changes = diff(&token("old-token"), &token("new-token"));
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].path, "api_token");
+        assert_eq!(changes[0].new, Value::from(crate::types::REDACTED));
+        assert!(diff(&token("same"), &token("same")).is_empty());
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn test_json_schema() {
+        let schema = json_schema();
+        let properties = &schema["properties"];
+        assert_eq!(properties["max_concurrent"]["minimum"], 1);
+        assert_eq!(properties["max_concurrent"]["default"], 4);
+        assert_eq!(properties["debug"]["description"], "Debug mode enabled");
+        assert!(properties["name"]["pattern"].is_string());
+        let profile = &properties["profiles"]["additionalProperties"]["properties"];
+        assert_eq!(profile["max_concurrent"]["minimum"], 1);
+    }
+
+    #[test]
+    fn test_secrets_are_redacted_everywhere() {
+        const TOKEN: &str = "hunter2-token";
+        let loaded = ConfigLoader::new()
+            .env([(format!("{}API_TOKEN", env_prefix()), TOKEN)])
+            .load()
+            .unwrap();
+        let token = loaded.config.api_token.as_ref().unwrap();
+        assert_eq!(token.expose(), TOKEN);
+
+        let changes = diff(&Config::default(), &loaded.config);
+        let outputs = [
+            format!("{:?}", loaded.config),
+            format!("{token} {token:?}"),
+            serde_json::to_string(&loaded.config).unwrap(),
+            format!("{:?}", loaded.fields()),
+            format!("{changes:?}"),
+        ];
+        for output in &outputs {
+            assert!(!output.contains(TOKEN), "leaked in {output}");
+        }
+        assert!(outputs[2].contains(crate::types::REDACTED));
+
+        let error = ConfigLoader::new()
+            .set("api_token", "   ")
+            .load()
+            .unwrap_err();
+        let report = crate::report::ErrorReport::new(&error);
+        assert!(report.to_string().contains(crate::types::REDACTED));
+        assert!(
+            report
+                .to_json()
+                .to_string()
+                .contains(crate::types::REDACTED)
+        );
+
+        let numeric = ConfigLoader::new()
+            .set("api_token", "12345")
+            .load()
+            .unwrap();
+        assert_eq!(numeric.config.api_token.unwrap().expose(), "12345");
+        let error = ConfigLoader::new()
+            .file(write_config(".json", r#"{"api_token": {"nested": "hunter2"}}"#).path())
+            .load()
+            .unwrap_err();
+        assert!(!error.to_string().contains("hunter2"));
+    }
+
+    #[test]
+    fn test_validates_merged_config() {
+        let result = ConfigLoader::new().set("max_concurrent", "0").load();
+        assert!(matches!(result, Err(Error::Validation(v)) if v[0].path == "max_concurrent"));
+    }
+}
@@ -1,22 +1,262 @@
 //! Error types and utilities
 
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
 use thiserror::Error;
 
 /// Core error type for {{PROJECT_NAME}}
 #[derive(Error, Debug)]
 pub enum Error {
     /// IO error
+    #[cfg(feature = "std")]
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
-    
+
     /// Configuration error
     #[error("Configuration error: {0}")]
     Config(String),
-    
+
+    /// One or more configuration values violate their constraints
+    #[error("Invalid configuration: {} violation(s)", .0.len())]
+    Validation(Vec<Violation>),
+
+    /// Invalid command-line usage
+    #[error("Usage error: {0}")]
+    Usage(String),
+
+    /// Plugin registration, ordering or lifecycle failure
+    #[error("Plugin error: {0}")]
+    Plugin(String),
+
+    /// Persisted state snapshot cannot be used
+    #[error("Snapshot error: {0}")]
+    Snapshot(String),
+
+    /// Service ordering or lifecycle failure
+    #[error("Service error: {0}")]
+    Service(String),
+
+    /// Service failed to start or stop; categorized like its cause
+    #[error("Service {service} failed to {action}: {source}")]
+    ServiceFailed {
+        /// Name of the service
+        service: &'static str,
+        /// `start` or `stop`
+        action: &'static str,
+        /// Why it failed
+        #[source]
+        source: Box<Error>,
+    },
+
+    /// Malformed input at a position, both starting at 1
+    #[error("Parse error at line {line}, column {column}: {message}")]
+    Parse {
+        /// Line of the input
+        line: u64,
+        /// Character within the line
+        column: u64,
+        /// What is wrong
+        message: String,
+    },
+
     /// Generic error with message
     #[error("{0}")]
     Other(String),
 }
 
+impl Error {
+    /// Stable machine-readable code; never renumber or reuse a code
+    pub const fn code(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "std")]
+            Self::Io(_) => "E0001",
+            Self::Config(_) => "E0002",
+            Self::Validation(_) => "E0003",
+            Self::Other(_) => "E0004",
+            Self::Usage(_) => "E0005",
+            Self::Plugin(_) => "E0006",
+            Self::Snapshot(_) => "E0007",
+            Self::Service(_) => "E0008",
+            Self::Parse { .. } => "E0009",
+            Self::ServiceFailed { .. } => "E0010",
+        }
+    }
+
+    /// Name of the variant, e.g. `Config`
+    pub const fn name(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "std")]
+            Self::Io(_) => "Io",
+            Self::Config(_) => "Config",
+            Self::Validation(_) => "Validation",
+            Self::Other(_) => "Other",
+            Self::Usage(_) => "Usage",
+            Self::Plugin(_) => "Plugin",
+            Self::Snapshot(_) => "Snapshot",
+            Self::Service(_) => "Service",
+            Self::Parse { .. } => "Parse",
+            Self::ServiceFailed { .. } => "ServiceFailed",
+        }
+    }
+
+    /// Broad class of the error
+    pub const fn category(&self) -> ErrorCategory {
+        match self {
+            #[cfg(feature = "std")]
+            Self::Io(_) => ErrorCategory::Io,
+            Self::Config(_) | Self::Validation(_) => ErrorCategory::Config,
+            Self::Usage(_) => ErrorCategory::Usage,
+            Self::Snapshot(_) | Self::Parse { .. } => ErrorCategory::Data,
+            Self::Plugin(_) | Self::Service(_) | Self::Other(_) => ErrorCategory::Internal,
+            Self::ServiceFailed { source, .. } => source.category(),
+        }
+    }
+
+    /// Process exit code for this error
+    pub const fn exit_code(&self) -> u8 {
+        self.category().exit_code()
+    }
+}
+
+/// Broad class of an error, mapped to a sysexits-style exit code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+    /// The command was used incorrectly (`EX_USAGE`)
+    Usage,
+    /// Input data was malformed (`EX_DATAERR`)
+    Data,
+    /// Unexpected internal failure (`EX_SOFTWARE`)
+    Internal,
+    /// Reading or writing failed (`EX_IOERR`)
+    Io,
+    /// Configuration is missing or invalid (`EX_CONFIG`)
+    Config,
+}
+
+impl ErrorCategory {
+    /// sysexits-style process exit code
+    pub const fn exit_code(self) -> u8 {
+        match self {
+            Self::Usage => 64,
+            Self::Data => 65,
+            Self::Internal => 70,
+            Self::Io => 74,
+            Self::Config => 78,
+        }
+    }
+
+    /// Lowercase name, e.g. `config`
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Usage => "usage",
+            Self::Data => "data",
+            Self::Internal => "internal",
+            Self::Io => "io",
+            Self::Config => "config",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A single constraint violation found while validating configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// Dotted path of the offending field, e.g. `max_concurrent`
+    pub path: String,
+    /// Offending value as it would appear in a config file
+    pub value: String,
+    /// Description of the violated constraint
+    pub constraint: String,
+    /// Suggestion for fixing the value
+    pub hint: String,
+}
+
+impl Violation {
+    /// Create a violation for the field at `path`
+    pub fn new(
+        path: impl Into<String>,
+        value: impl fmt::Debug,
+        constraint: impl Into<String>,
+        hint: impl Into<String>,
+    ) -> Self {
+        Self {
+            path: path.into(),
+            value: format!("{value:?}"),
+            constraint: constraint.into(),
+            hint: hint.into(),
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} = {}: {} (hint: {})",
+            self.path, self.value, self.constraint, self.hint
+        )
+    }
+}
+
 /// Result type alias using our Error
-pub type Result<T> = std::result::Result<T, Error>;
\ No newline at end of file
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_codes_and_exit_codes() {
+        let config = Error::Config(String::new());
+        assert_eq!(config.code(), "E0002");
+        assert_eq!(config.name(), "Config");
+        assert_eq!(config.category(), ErrorCategory::Config);
+        assert_eq!(config.exit_code(), 78);
+        assert_eq!(Error::Validation(Vec::new()).exit_code(), 78);
+        assert_eq!(Error::Usage(String::new()).exit_code(), 64);
+        assert_eq!(Error::Other(String::new()).exit_code(), 70);
+        assert_eq!(Error::Snapshot(String::new()).exit_code(), 65);
+        assert_eq!(Error::Service(String::new()).code(), "E0008");
+        assert_eq!(Error::Service(String::new()).exit_code(), 70);
+        let parse = Error::Parse {
+            line: 3,
+            column: 7,
+            message: "expected value".to_string(),
+        };
+        assert_eq!(parse.code(), "E0009");
+        let failed = Error::ServiceFailed {
+            service: "db",
+            action: "start",
+            source: Box::new(Error::Snapshot("too new".to_string())),
+        };
+        assert_eq!(failed.code(), "E0010");
+        assert_eq!(failed.exit_code(), 65);
+        assert_eq!(
+            failed.to_string(),
+            "Service db failed to start: Snapshot error: too new"
+        );
+        assert_eq!(parse.exit_code(), 65);
+        assert_eq!(
+            parse.to_string(),
+            "Parse error at line 3, column 7: expected value"
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_io_is_distinct_from_config() {
+        let io = Error::from(std::io::Error::other("disk"));
+        assert_eq!(io.code(), "E0001");
+        assert_eq!(io.exit_code(), 74);
+    }
+}
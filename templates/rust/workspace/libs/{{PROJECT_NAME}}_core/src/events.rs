@@ -0,0 +1,511 @@
+//! Publish/subscribe bus for lifecycle events
+//!
+//! Handlers registered with [`EventBus::on_event`] run synchronously on the
+//! publishing thread. Every [`Subscription`] and [`AsyncSubscription`] gets its
+//! own bounded buffer instead. When a buffer is full, its [`OverflowPolicy`]
+//! decides whether the publisher drops an event or waits, so a slow subscriber
+//! never affects the others unless it asks to.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::config::FieldChange;
+use crate::operations::OperationId;
+use crate::types::Config;
+
+/// Something that happened in the core
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// A configuration was put into effect, at startup or on reload
+    ConfigLoaded {
+        /// Configuration now in effect
+        config: Arc<Config>,
+        /// Fields that changed; empty at startup
+        changes: Vec<FieldChange>,
+    },
+    /// An operation was registered
+    OperationStarted {
+        /// Identifier of the operation
+        id: OperationId,
+        /// Name of the operation
+        name: String,
+    },
+    /// An operation handle was dropped
+    OperationFinished {
+        /// Identifier of the operation
+        id: OperationId,
+        /// Name of the operation
+        name: String,
+        /// How long the operation ran
+        duration: Duration,
+        /// Whether cancellation had been requested
+        cancelled: bool,
+    },
+    /// Shutdown was requested by a signal or programmatically
+    ShutdownRequested,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ConfigLoaded { changes, .. } if changes.is_empty() => {
+                write!(f, "configuration loaded")
+            }
+            Self::ConfigLoaded { changes, .. } => {
+                write!(f, "configuration loaded ({} changed)", changes.len())
+            }
+            Self::OperationStarted { id, name } => write!(f, "{id} {name} started"),
+            Self::OperationFinished {
+                id,
+                name,
+                duration,
+                cancelled,
+            } => {
+                let verb = if *cancelled { "cancelled" } else { "finished" };
+                write!(f, "{id} {name} {verb} after {:.1}s", duration.as_secs_f32())
+            }
+            Self::ShutdownRequested => write!(f, "shutdown requested"),
+        }
+    }
+}
+
+/// What to do when a subscriber's buffer is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Discard the oldest buffered event to make room
+    #[default]
+    DropOldest,
+    /// Discard the event being published
+    DropNewest,
+    /// Make the publisher wait for room; the subscriber must keep receiving
+    Block,
+}
+
+/// Buffering of a single subscriber
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubscriberOptions {
+    /// Maximum number of buffered events
+    pub capacity: usize,
+    /// What to do when the buffer is full
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for SubscriberOptions {
+    fn default() -> Self {
+        Self {
+            capacity: 256,
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    events: VecDeque<Event>,
+    dropped: u64,
+    closed: bool,
+}
+
+#[derive(Debug)]
+struct Buffer {
+    options: SubscriberOptions,
+    queue: Mutex<Queue>,
+    readable: Condvar,
+    writable: Condvar,
+    #[cfg(feature = "async")]
+    notify: tokio::sync::Notify,
+}
+
+impl Buffer {
+    fn new(options: SubscriberOptions) -> Self {
+        Self {
+            options: SubscriberOptions {
+                capacity: options.capacity.max(1),
+                ..options
+            },
+            queue: Mutex::new(Queue::default()),
+            readable: Condvar::new(),
+            writable: Condvar::new(),
+            #[cfg(feature = "async")]
+            notify: tokio::sync::Notify::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wake_reader(&self) {
+        self.readable.notify_one();
+        #[cfg(feature = "async")]
+        self.notify.notify_one();
+    }
+
+    fn push(&self, event: Event) {
+        let mut queue = self.lock();
+        while queue.events.len() >= self.options.capacity && !queue.closed {
+            match self.options.overflow {
+                OverflowPolicy::DropOldest => {
+                    queue.events.pop_front();
+                    queue.dropped += 1;
+                }
+                OverflowPolicy::DropNewest => {
+                    queue.dropped += 1;
+                    return;
+                }
+                OverflowPolicy::Block => {
+                    queue = self
+                        .writable
+                        .wait(queue)
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+            }
+        }
+        if !queue.closed {
+            queue.events.push_back(event);
+            drop(queue);
+            self.wake_reader();
+        }
+    }
+
+    /// Pop an event; `Err(true)` once closed and drained, `Err(false)` if merely empty
+    fn pop(&self, queue: &mut Queue) -> Result<Event, bool> {
+        match queue.events.pop_front() {
+            Some(event) => {
+                self.writable.notify_one();
+                Ok(event)
+            }
+            None => Err(queue.closed),
+        }
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.readable.notify_all();
+        self.writable.notify_all();
+        #[cfg(feature = "async")]
+        self.notify.notify_one();
+    }
+}
+
+type Handler = Arc<dyn Fn(&Event) + Send + Sync>;
+
+#[derive(Default)]
+struct Inner {
+    handlers: Mutex<Vec<Handler>>,
+    buffers: Mutex<Vec<Arc<Buffer>>>,
+}
+
+impl Inner {
+    fn handlers(&self) -> MutexGuard<'_, Vec<Handler>> {
+        self.handlers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn buffers(&self) -> MutexGuard<'_, Vec<Arc<Buffer>>> {
+        self.buffers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn attach(&self, options: SubscriberOptions) -> Arc<Buffer> {
+        let buffer = Arc::new(Buffer::new(options));
+        self.buffers().push(Arc::clone(&buffer));
+        buffer
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        for buffer in self.buffers().drain(..) {
+            buffer.close();
+        }
+    }
+}
+
+/// Cloneable handle to publish events and add subscribers
+///
+/// Subscriptions end once every handle of the bus has been dropped.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    inner: Arc<Inner>,
+}
+
+impl fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventBus")
+            .field("handlers", &self.inner.handlers().len())
+            .field("subscriptions", &self.inner.buffers().len())
+            .finish()
+    }
+}
+
+impl EventBus {
+    /// Create a bus without subscribers
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Deliver `event` to every handler and current subscriber
+    pub fn publish(&self, event: Event) {
+        // Deliver outside the locks so handlers and blocking subscribers can subscribe
+        let handlers = self.inner.handlers().clone();
+        for handler in handlers {
+            handler(&event);
+        }
+        let buffers: Vec<_> = {
+            let mut buffers = self.inner.buffers();
+            buffers.retain(|buffer| Arc::strong_count(buffer) > 1);
+            buffers.clone()
+        };
+        for buffer in buffers {
+            buffer.push(event.clone());
+        }
+    }
+
+    /// Call `handler` on the publishing thread for every event from now on
+    ///
+    /// Handlers must be quick; they delay the publisher and every later handler.
+    pub fn on_event(&self, handler: impl Fn(&Event) + Send + Sync + 'static) {
+        self.inner.handlers().push(Arc::new(handler));
+    }
+
+    /// Receive events published from now on, blocking the calling thread
+    pub fn subscribe(&self, options: SubscriberOptions) -> Subscription {
+        Subscription {
+            buffer: self.inner.attach(options),
+        }
+    }
+
+    /// Receive events published from now on, in async code
+    #[cfg(feature = "async")]
+    pub fn subscribe_async(&self, options: SubscriberOptions) -> AsyncSubscription {
+        AsyncSubscription {
+            buffer: self.inner.attach(options),
+        }
+    }
+}
+
+/// Blocking receiver of events; unsubscribes when dropped
+#[derive(Debug)]
+pub struct Subscription {
+    buffer: Arc<Buffer>,
+}
+
+impl Subscription {
+    /// Wait for the next event, or `None` once the bus is gone
+    pub fn recv(&self) -> Option<Event> {
+        let mut queue = self.buffer.lock();
+        loop {
+            match self.buffer.pop(&mut queue) {
+                Ok(event) => return Some(event),
+                Err(true) => return None,
+                Err(false) => {
+                    queue = self
+                        .buffer
+                        .readable
+                        .wait(queue)
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for the next event
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Event> {
+        let deadline = Instant::now() + timeout;
+        let mut queue = self.buffer.lock();
+        loop {
+            match self.buffer.pop(&mut queue) {
+                Ok(event) => return Some(event),
+                Err(true) => return None,
+                Err(false) => {
+                    let remaining = deadline.checked_duration_since(Instant::now())?;
+                    queue = self
+                        .buffer
+                        .readable
+                        .wait_timeout(queue, remaining)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0;
+                }
+            }
+        }
+    }
+
+    /// Take the next event if one is buffered
+    pub fn try_recv(&self) -> Option<Event> {
+        self.buffer.pop(&mut self.buffer.lock()).ok()
+    }
+
+    /// Iterate over the events buffered right now, without waiting
+    pub fn try_iter(&self) -> impl Iterator<Item = Event> + '_ {
+        std::iter::from_fn(|| self.try_recv())
+    }
+
+    /// Number of events discarded because the buffer was full
+    pub fn dropped(&self) -> u64 {
+        self.buffer.lock().dropped
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        self.recv()
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // Release a publisher waiting on a full buffer
+        self.buffer.close();
+    }
+}
+
+/// Async receiver of events; unsubscribes when dropped
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct AsyncSubscription {
+    buffer: Arc<Buffer>,
+}
+
+#[cfg(feature = "async")]
+impl AsyncSubscription {
+    /// Wait for the next event, or `None` once the bus is gone
+    pub async fn recv(&self) -> Option<Event> {
+        loop {
+            let popped = self.buffer.pop(&mut self.buffer.lock());
+            match popped {
+                Ok(event) => return Some(event),
+                Err(true) => return None,
+                Err(false) => self.buffer.notify.notified().await,
+            }
+        }
+    }
+
+    /// Take the next event if one is buffered
+    pub fn try_recv(&self) -> Option<Event> {
+        self.buffer.pop(&mut self.buffer.lock()).ok()
+    }
+
+    /// Number of events discarded because the buffer was full
+    pub fn dropped(&self) -> u64 {
+        self.buffer.lock().dropped
+    }
+}
+
+#[cfg(feature = "async")]
+impl Drop for AsyncSubscription {
+    fn drop(&mut self) {
+        self.buffer.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn started(n: &str) -> Event {
+        Event::OperationStarted {
+            id: "op-1".parse().unwrap(),
+            name: n.to_string(),
+        }
+    }
+
+    fn name(event: Event) -> String {
+        match event {
+            Event::OperationStarted { name, .. } => name,
+            other => panic!("unexpected event: {other}"),
+        }
+    }
+
+    fn options(capacity: usize, overflow: OverflowPolicy) -> SubscriberOptions {
+        SubscriberOptions { capacity, overflow }
+    }
+
+    #[test]
+    fn test_every_subscriber_receives_events() {
+        let bus = EventBus::new();
+        let first = bus.subscribe(SubscriberOptions::default());
+        let second = bus.subscribe(SubscriberOptions::default());
+        bus.publish(Event::ShutdownRequested);
+
+        assert!(matches!(first.try_recv(), Some(Event::ShutdownRequested)));
+        assert!(matches!(second.try_recv(), Some(Event::ShutdownRequested)));
+        assert!(first.try_recv().is_none());
+
+        drop(second);
+        bus.publish(Event::ShutdownRequested);
+        assert_eq!(bus.inner.buffers().len(), 1);
+
+        drop(bus);
+        assert!(first.recv().is_some());
+        assert!(first.recv().is_none());
+    }
+
+    #[test]
+    fn test_handlers_run_on_publish() {
+        let bus = EventBus::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        bus.on_event(move |event| sink.lock().unwrap().push(event.to_string()));
+
+        bus.publish(started("a"));
+        bus.publish(Event::ShutdownRequested);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            ["op-1 a started", "shutdown requested"]
+        );
+    }
+
+    #[test]
+    fn test_overflow_policies() {
+        let bus = EventBus::new();
+        let oldest = bus.subscribe(options(2, OverflowPolicy::DropOldest));
+        let newest = bus.subscribe(options(2, OverflowPolicy::DropNewest));
+        for n in ["a", "b", "c"] {
+            bus.publish(started(n));
+        }
+        drop(bus);
+
+        assert_eq!(oldest.dropped(), 1);
+        assert_eq!(oldest.map(name).collect::<Vec<_>>(), ["b", "c"]);
+        assert_eq!(newest.dropped(), 1);
+        assert_eq!(newest.map(name).collect::<Vec<_>>(), ["a", "b"]);
+    }
+
+    #[test]
+    fn test_block_policy_waits_for_room() {
+        let bus = EventBus::new();
+        let subscription = bus.subscribe(options(1, OverflowPolicy::Block));
+        let publisher = thread::spawn(move || {
+            for n in ["a", "b", "c"] {
+                bus.publish(started(n));
+            }
+        });
+
+        let received: Vec<_> = subscription.map(name).collect();
+        publisher.join().unwrap();
+        assert_eq!(received, ["a", "b", "c"]);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_subscriber() {
+        let bus = EventBus::new();
+        let subscription = bus.subscribe_async(SubscriberOptions::default());
+        let publisher = tokio::task::spawn_blocking(move || {
+            bus.publish(started("a"));
+            bus.publish(Event::ShutdownRequested);
+        });
+
+        assert_eq!(name(subscription.recv().await.unwrap()), "a");
+        assert!(matches!(
+            subscription.recv().await,
+            Some(Event::ShutdownRequested)
+        ));
+        publisher.await.unwrap();
+        assert!(subscription.recv().await.is_none());
+    }
+}
@@ -0,0 +1,407 @@
+//! Runtime feature flags
+//!
+//! Flags are declared in code with [`register_flag!`](crate::register_flag),
+//! each with a default and a description. The default can be overridden, in
+//! increasing order of precedence, by the `flags` table of [`Config`],
+//! `<PREFIX>FLAG_<NAME>` environment variables, where `<PREFIX>` is
+//! [`env_prefix`], and the command line.
+//! A setting is either on, off, or a percentage rollout that enables the flag
+//! for a stable share of rollout keys such as user ids.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::config::env_prefix;
+use crate::types::{Config, FlagSetting};
+use crate::{Error, Result};
+
+#[doc(hidden)]
+pub use inventory;
+
+/// Feature flag declared in code
+#[derive(Debug, PartialEq, Eq)]
+pub struct Flag {
+    name: &'static str,
+    description: &'static str,
+    default: bool,
+}
+
+impl Flag {
+    /// Declare a flag that is `default` unless overridden
+    pub const fn new(name: &'static str, description: &'static str, default: bool) -> Self {
+        Self {
+            name,
+            description,
+            default,
+        }
+    }
+
+    /// Unique flag name, used in configuration, environment and CLI
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// What the flag enables
+    pub const fn description(&self) -> &'static str {
+        self.description
+    }
+
+    /// Whether the flag is on without overrides
+    pub const fn default(&self) -> bool {
+        self.default
+    }
+}
+
+/// Entry in the compile-time flag registry, created by [`register_flag!`](crate::register_flag)
+pub struct FlagRegistration {
+    flag: &'static Flag,
+}
+
+impl FlagRegistration {
+    /// Wrap a flag for submission to the registry
+    pub const fn new(flag: &'static Flag) -> Self {
+        Self { flag }
+    }
+}
+
+inventory::collect!(FlagRegistration);
+
+/// Add a flag to the compile-time registry
+///
+/// ```ignore
+/// pub static NEW_PARSER: Flag = Flag::new("new_parser", "Use the rewritten parser", false);
+/// register_flag!(NEW_PARSER);
+///
+/// if state.flag(&NEW_PARSER).is_enabled() {
+///     // ...
+/// }
+/// ```
+#[macro_export]
+macro_rules! register_flag {
+    ($flag:path) => {
+        $crate::flags::inventory::submit! {
+            $crate::flags::FlagRegistration::new(&$flag)
+        }
+    };
+}
+
+/// Every flag registered in the binary, sorted by name
+pub fn registered() -> Vec<&'static Flag> {
+    let mut flags: Vec<_> = inventory::iter::<FlagRegistration>
+        .into_iter()
+        .map(|registration| registration.flag)
+        .collect();
+    flags.sort_by_key(|flag| flag.name);
+    flags
+}
+
+/// Layer that decided the setting of a flag
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlagSource {
+    /// Declared default
+    Default,
+    /// `flags` table of the configuration
+    Config,
+    /// Environment variable with the given name
+    Env(String),
+    /// Command-line override
+    Cli,
+}
+
+impl fmt::Display for FlagSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => write!(f, "default"),
+            Self::Config => write!(f, "config"),
+            Self::Env(name) => write!(f, "env {name}"),
+            Self::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+/// Environment and command-line settings that take precedence over [`Config`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlagOverrides {
+    settings: BTreeMap<String, (FlagSetting, FlagSource)>,
+}
+
+impl FlagOverrides {
+    /// Create empty overrides
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read `<PREFIX>FLAG_<NAME>` variables, e.g. `on`, `off` or `25%`
+    ///
+    /// `<PREFIX>` is [`env_prefix`], the project name in upper case and `_`.
+    pub fn env<I, K, V>(mut self, vars: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let prefix = format!("{}FLAG_", env_prefix());
+        for (key, value) in vars {
+            let key = key.into();
+            let Some(name) = key.strip_prefix(&prefix) else {
+                continue;
+            };
+            let value = value.into();
+            let setting = FlagSetting::parse(&value).ok_or_else(|| {
+                Error::Config(format!(
+                    "invalid value for {key}: expected on, off or a percentage, got {value:?}"
+                ))
+            })?;
+            self.settings.insert(
+                name.to_ascii_lowercase(),
+                (setting, FlagSource::Env(key.clone())),
+            );
+        }
+        Ok(self)
+    }
+
+    /// Read the environment of the current process
+    pub fn process_env(self) -> Result<Self> {
+        self.env(std::env::vars())
+    }
+
+    /// Turn a flag on from the command line
+    pub fn enable(mut self, name: impl Into<String>) -> Self {
+        self.settings
+            .insert(name.into(), (FlagSetting::Enabled(true), FlagSource::Cli));
+        self
+    }
+
+    /// Turn a flag off from the command line
+    pub fn disable(mut self, name: impl Into<String>) -> Self {
+        self.settings
+            .insert(name.into(), (FlagSetting::Enabled(false), FlagSource::Cli));
+        self
+    }
+}
+
+/// Effective setting of a flag
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlagState {
+    /// The declared flag
+    pub flag: &'static Flag,
+    /// Setting in effect
+    pub setting: FlagSetting,
+    /// Layer the setting came from
+    pub source: FlagSource,
+}
+
+impl FlagState {
+    /// Whether the flag is on; a rollout below 100% needs a key and counts as off
+    pub fn is_enabled(&self) -> bool {
+        match self.setting {
+            FlagSetting::Enabled(enabled) => enabled,
+            FlagSetting::Rollout(percent) => percent >= 100,
+        }
+    }
+
+    /// Whether the flag is on for `key`, e.g. a user or tenant id
+    ///
+    /// The same key always lands in the same bucket, so raising the
+    /// percentage only ever adds keys.
+    pub fn is_enabled_for(&self, key: &str) -> bool {
+        match self.setting {
+            FlagSetting::Enabled(enabled) => enabled,
+            FlagSetting::Rollout(percent) => bucket(self.flag.name, key) < u64::from(percent),
+        }
+    }
+}
+
+/// Known flags together with the environment and command-line overrides
+#[derive(Debug, Clone, Default)]
+pub struct FeatureFlags {
+    flags: Vec<&'static Flag>,
+    overrides: FlagOverrides,
+}
+
+impl FeatureFlags {
+    /// Manage `flags` without overrides
+    pub fn new(flags: impl IntoIterator<Item = &'static Flag>) -> Self {
+        let mut flags: Vec<_> = flags.into_iter().collect();
+        flags.sort_by_key(|flag| flag.name);
+        flags.dedup_by_key(|flag| flag.name);
+        Self {
+            flags,
+            overrides: FlagOverrides::default(),
+        }
+    }
+
+    /// Manage every flag registered in the binary
+    pub fn registered() -> Self {
+        Self::new(registered())
+    }
+
+    /// Apply `overrides`, rejecting names of undeclared flags
+    pub fn with_overrides(mut self, overrides: FlagOverrides) -> Result<Self> {
+        for name in overrides.settings.keys() {
+            self.check_known(name)?;
+        }
+        self.overrides = overrides;
+        Ok(self)
+    }
+
+    /// Reject settings in `config` for undeclared flags
+    pub fn check(&self, config: &Config) -> Result<()> {
+        config
+            .flags
+            .keys()
+            .try_for_each(|name| self.check_known(name))
+    }
+
+    fn check_known(&self, name: &str) -> Result<()> {
+        if self.flags.iter().any(|flag| flag.name == name) {
+            return Ok(());
+        }
+        let known: Vec<_> = self.flags.iter().map(|flag| flag.name).collect();
+        let known = if known.is_empty() {
+            "none declared".to_string()
+        } else {
+            known.join(", ")
+        };
+        Err(Error::Config(format!(
+            "unknown feature flag {name:?} (known flags: {known})"
+        )))
+    }
+
+    /// Effective setting of `flag` under `config`
+    pub fn state(&self, flag: &'static Flag, config: &Config) -> FlagState {
+        let (setting, source) = match self.overrides.settings.get(flag.name) {
+            Some((setting, source)) => (*setting, source.clone()),
+            None => match config.flags.get(flag.name) {
+                Some(setting) => (*setting, FlagSource::Config),
+                None => (FlagSetting::Enabled(flag.default), FlagSource::Default),
+            },
+        };
+        FlagState {
+            flag,
+            setting,
+            source,
+        }
+    }
+
+    /// Effective setting of every known flag under `config`, sorted by name
+    pub fn states(&self, config: &Config) -> Vec<FlagState> {
+        self.flags
+            .iter()
+            .map(|flag| self.state(flag, config))
+            .collect()
+    }
+}
+
+/// Stable bucket in `0..100` of `key` for the flag `name` (64-bit FNV-1a)
+///
+/// Hashing the flag name too keeps rollouts of different flags independent.
+fn bucket(name: &str, key: &str) -> u64 {
+    const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    let hash = name
+        .bytes()
+        .chain([0])
+        .chain(key.bytes())
+        .fold(OFFSET, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+        });
+    hash % 100
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static NEW_PARSER: Flag = Flag::new("new_parser", "Use the rewritten parser", false);
+    static FAST_PATH: Flag = Flag::new("fast_path", "Skip redundant checks", true);
+    crate::register_flag!(NEW_PARSER);
+
+    fn declared() -> FeatureFlags {
+        FeatureFlags::new([&NEW_PARSER, &FAST_PATH])
+    }
+
+    #[test]
+    fn test_registered_flags() {
+        assert!(registered().contains(&&NEW_PARSER));
+    }
+
+    #[test]
+    fn test_override_precedence() {
+        let mut config = Config::default();
+        config
+            .flags
+            .insert("new_parser".to_string(), FlagSetting::Enabled(true));
+        config
+            .flags
+            .insert("fast_path".to_string(), FlagSetting::Enabled(false));
+        let env = format!("{}FLAG_FAST_PATH", env_prefix());
+        let overrides = FlagOverrides::new()
+            .env([(env.clone(), "on"), ("UNRELATED".to_string(), "x")])
+            .unwrap();
+
+        let flags = declared().with_overrides(overrides.clone()).unwrap();
+        let states = flags.states(&config);
+        assert_eq!(states[0].flag.name(), "fast_path");
+        assert!(states[0].is_enabled());
+        assert_eq!(states[0].source, FlagSource::Env(env));
+        assert!(states[1].is_enabled());
+        assert_eq!(states[1].source, FlagSource::Config);
+
+        let flags = declared()
+            .with_overrides(overrides.disable("new_parser"))
+            .unwrap();
+        let state = flags.state(&NEW_PARSER, &config);
+        assert!(!state.is_enabled());
+        assert_eq!(state.source, FlagSource::Cli);
+        assert!(!flags.state(&NEW_PARSER, &Config::default()).is_enabled());
+    }
+
+    #[test]
+    fn test_unknown_flags_are_rejected() {
+        let Err(Error::Config(message)) =
+            declared().with_overrides(FlagOverrides::new().enable("nope"))
+        else {
+            panic!("expected unknown flag error");
+        };
+        assert!(message.contains("fast_path, new_parser"));
+
+        let mut config = Config::default();
+        config
+            .flags
+            .insert("nope".to_string(), FlagSetting::Enabled(true));
+        assert!(declared().check(&config).is_err());
+        assert!(
+            FlagOverrides::new()
+                .env([(format!("{}FLAG_NEW_PARSER", env_prefix()), "maybe")])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_percentage_rollout() {
+        let state = |percent| FlagState {
+            flag: &NEW_PARSER,
+            setting: FlagSetting::Rollout(percent),
+            source: FlagSource::Config,
+        };
+        let keys: Vec<String> = (0..1000).map(|i| format!("user-{i}")).collect();
+        let enabled = |percent| {
+            keys.iter()
+                .filter(|key| state(percent).is_enabled_for(key))
+                .count()
+        };
+        assert_eq!(enabled(0), 0);
+        assert_eq!(enabled(100), 1000);
+        assert!((200..300).contains(&enabled(25)));
+
+        // Raising the percentage keeps every key that was already enabled
+        assert!(
+            keys.iter()
+                .filter(|key| state(25).is_enabled_for(key))
+                .all(|key| state(50).is_enabled_for(key))
+        );
+        assert!(!state(25).is_enabled());
+        assert!(state(100).is_enabled());
+    }
+}
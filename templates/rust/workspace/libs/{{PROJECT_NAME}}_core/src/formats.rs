@@ -0,0 +1,1075 @@
+//! Input formats of the record pipeline
+//!
+//! An [`InputFormat`] parses an input stream into [`Record`]s. JSON, NDJSON,
+//! CSV, TOML and plain text are built in; other formats are added with
+//! [`register_format!`](crate::register_format). The format of an input is
+//! taken from its file extension, or else detected from its first few
+//! kilobytes, falling back to plain text. Malformed input is reported as
+//! [`Error::Parse`] with the line and column of the problem.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, Cursor, Read};
+use std::path::Path;
+
+use serde::de::IgnoredAny;
+
+use crate::pipeline::OutputFormat;
+use crate::types::{Record, Value};
+use crate::{Error, Result};
+
+#[doc(hidden)]
+pub use inventory;
+
+/// Input stream handed to an [`InputFormat`]
+pub type Input<'a> = Box<dyn BufRead + Send + 'a>;
+
+/// Records parsed from an input, in order
+///
+/// Malformed records are [`Error::Parse`] items. Formats that can resume
+/// after one, such as NDJSON, keep going; the others end the iteration.
+pub type Records<'a> = Box<dyn Iterator<Item = Result<Record>> + Send + 'a>;
+
+/// Bytes at the start of an input inspected to detect its format
+const SAMPLE_SIZE: usize = 8 * 1024;
+
+/// Way of parsing an input into records
+pub trait InputFormat: Send + Sync {
+    /// Unique lowercase name, e.g. `csv`
+    fn name(&self) -> &'static str;
+
+    /// File extensions, without the dot, of inputs in this format
+    fn extensions(&self) -> &'static [&'static str];
+
+    /// How likely it is that an input starting with `sample` is in this format,
+    /// from 0 (not at all) to 100
+    ///
+    /// `sample` ends at a line break unless it is the whole input.
+    fn sniff(&self, sample: &str) -> u8;
+
+    /// Parse `input` into records
+    fn records<'a>(&self, input: Input<'a>) -> Records<'a>;
+
+    /// How records read in this format are written out by default
+    fn output_format(&self) -> OutputFormat {
+        OutputFormat::JsonLines
+    }
+}
+
+/// Entry in the compile-time format registry, created by [`register_format!`](crate::register_format)
+pub struct FormatRegistration {
+    format: &'static dyn InputFormat,
+}
+
+impl FormatRegistration {
+    /// Wrap a format for submission to the registry
+    pub const fn new(format: &'static dyn InputFormat) -> Self {
+        Self { format }
+    }
+}
+
+inventory::collect!(FormatRegistration);
+
+/// Add an input format to the compile-time registry
+///
+/// ```ignore
+/// pub struct Tsv;
+///
+/// impl InputFormat for Tsv {
+///     // ...
+/// }
+///
+/// register_format!(Tsv);
+/// ```
+#[macro_export]
+macro_rules! register_format {
+    ($format:path) => {
+        $crate::formats::inventory::submit! {
+            $crate::formats::FormatRegistration::new(&$format)
+        }
+    };
+}
+
+register_format!(Csv);
+register_format!(Json);
+register_format!(Ndjson);
+register_format!(Text);
+register_format!(Toml);
+
+/// Every format registered in the binary, sorted by name
+pub fn registered() -> Vec<&'static dyn InputFormat> {
+    let mut formats: Vec<_> = inventory::iter::<FormatRegistration>
+        .into_iter()
+        .map(|registration| registration.format)
+        .collect();
+    formats.sort_by_key(|format| format.name());
+    formats
+}
+
+fn known() -> String {
+    registered()
+        .iter()
+        .map(|format| format.name())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Registered format called `name`
+pub fn by_name(name: &str) -> Result<&'static dyn InputFormat> {
+    registered()
+        .into_iter()
+        .find(|format| format.name().eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            Error::Usage(format!(
+                "unknown input format {name:?} (known formats: {})",
+                known()
+            ))
+        })
+}
+
+/// Registered format of files with the extension of `path`
+pub fn by_extension(path: &Path) -> Option<&'static dyn InputFormat> {
+    let extension = path.extension()?.to_str()?;
+    registered().into_iter().find(|format| {
+        format
+            .extensions()
+            .iter()
+            .any(|known| known.eq_ignore_ascii_case(extension))
+    })
+}
+
+/// Format of `input`, from the extension of `path` if it has a known one, else from the content
+///
+/// Input that no format recognizes is read as [`Text`]. Returns the input
+/// again, still positioned at its start.
+pub fn detect<'a>(
+    path: Option<&Path>,
+    mut input: Input<'a>,
+) -> Result<(&'static dyn InputFormat, Input<'a>)> {
+    if let Some(format) = path.and_then(by_extension) {
+        return Ok((format, input));
+    }
+
+    let mut sample = Vec::with_capacity(SAMPLE_SIZE);
+    (&mut input)
+        .take(SAMPLE_SIZE as u64)
+        .read_to_end(&mut sample)?;
+    let text = String::from_utf8_lossy(&sample);
+    // A truncated sample may end in the middle of a record
+    let text = match text.rfind('\n') {
+        Some(end) if sample.len() == SAMPLE_SIZE => &text[..=end],
+        _ => &text[..],
+    };
+    let mut best = None;
+    for format in registered() {
+        let score = format.sniff(text);
+        if score > best.map_or(0, |(best, _)| best) {
+            best = Some((score, format));
+        }
+    }
+
+    let input: Input<'a> = Box::new(Cursor::new(sample).chain(input));
+    Ok((best.map_or(&Text, |(_, format)| format), input))
+}
+
+/// Line and column, both starting at 1, of the byte `offset` in `text`
+fn position(text: &str, offset: usize) -> (u64, u64) {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count() + 1;
+    let column = before[line_start..].chars().count() + 1;
+    (line as u64, column as u64)
+}
+
+/// Parse error at a line and column
+fn parse_error((line, column): (u64, u64), message: impl Into<String>) -> Error {
+    Error::Parse {
+        line,
+        column,
+        message: message.into(),
+    }
+}
+
+/// Parse error at the byte `offset` in `text`
+fn error_at(text: &str, offset: usize, message: impl Into<String>) -> Error {
+    parse_error(position(text, offset), message)
+}
+
+/// Lines of an input with their numbers, starting at 1, without line endings
+struct Lines<'a> {
+    input: Input<'a>,
+    line: u64,
+    done: bool,
+}
+
+impl<'a> Lines<'a> {
+    fn new(input: Input<'a>) -> Self {
+        Self {
+            input,
+            line: 0,
+            done: false,
+        }
+    }
+}
+
+impl Iterator for Lines<'_> {
+    type Item = Result<(u64, String)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut buffer = Vec::new();
+        match self.input.read_until(b'\n', &mut buffer) {
+            Ok(0) => None,
+            Ok(_) => {
+                self.line += 1;
+                if buffer.ends_with(b"\n") {
+                    buffer.pop();
+                    if buffer.ends_with(b"\r") {
+                        buffer.pop();
+                    }
+                }
+                Some(match String::from_utf8(buffer) {
+                    Ok(text) => Ok((self.line, text)),
+                    Err(e) => {
+                        let valid = e.utf8_error().valid_up_to();
+                        let column = String::from_utf8_lossy(&e.as_bytes()[..valid])
+                            .chars()
+                            .count();
+                        Err(Error::Parse {
+                            line: self.line,
+                            column: column as u64 + 1,
+                            message: "invalid UTF-8".to_string(),
+                        })
+                    }
+                })
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e.into()))
+            }
+        }
+    }
+}
+
+/// Read the whole of `input` as text
+fn read_document(mut input: Input<'_>) -> Result<String> {
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|e| {
+        let valid = e.utf8_error().valid_up_to();
+        let text = String::from_utf8_lossy(&e.as_bytes()[..valid]).into_owned();
+        error_at(&text, text.len(), "invalid UTF-8")
+    })
+}
+
+/// Records of a document format, or the error that stopped reading it
+///
+/// The whole input is held in memory while parsing, which suits formats that
+/// cannot be split into records before they are parsed, such as TOML.
+fn document_records<'a>(
+    input: Input<'a>,
+    parse: impl FnOnce(&str) -> Vec<Result<Record>> + Send + 'a,
+) -> Records<'a> {
+    let records = match read_document(input) {
+        Ok(text) => parse(&text),
+        Err(e) => vec![Err(e)],
+    };
+    Box::new(records.into_iter())
+}
+
+fn json_value(value: serde_json::Value) -> Value {
+    match value {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Bool(b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => n.as_f64().map_or(Value::Null, Value::Float),
+        },
+        serde_json::Value::String(s) => Value::String(s),
+        serde_json::Value::Array(values) => {
+            Value::List(values.into_iter().map(json_value).collect())
+        }
+        serde_json::Value::Object(map) => Value::Map(
+            map.into_iter()
+                .map(|(key, value)| (key, json_value(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Record from the JSON value whose first character is at the line and column `start`
+fn json_record(start: (u64, u64), value: serde_json::Value) -> Result<Record> {
+    let kind = match value {
+        serde_json::Value::Object(map) => {
+            return Ok(Record {
+                line: start.0,
+                fields: map
+                    .into_iter()
+                    .map(|(key, value)| (key, json_value(value)))
+                    .collect(),
+            });
+        }
+        serde_json::Value::Array(_) => "an array",
+        serde_json::Value::String(_) => "a string",
+        serde_json::Value::Number(_) => "a number",
+        serde_json::Value::Bool(_) => "a boolean",
+        serde_json::Value::Null => "null",
+    };
+    Err(parse_error(
+        start,
+        format!("expected an object, found {kind}"),
+    ))
+}
+
+/// Parse error for `error`, raised while parsing text that starts at the line and column `start`
+fn json_error(start: (u64, u64), error: &serde_json::Error) -> Error {
+    let (line, column) = start;
+    let message = error.to_string();
+    // serde_json appends the position, which is relative to `start`
+    let message = message
+        .rsplit_once(" at line ")
+        .map_or(message.as_str(), |(message, _)| message);
+    let (line, column) = match error.line() {
+        0 | 1 => (line, column + error.column().saturating_sub(1) as u64),
+        relative => (line + relative as u64 - 1, error.column() as u64),
+    };
+    // serde_json reports column 0 at the start of a line, e.g. at the end of the input
+    parse_error((line, column.max(1)), message)
+}
+
+/// Records of a JSON document, read from the input one array element at a time
+///
+/// Tracks the line and column of the next byte, both starting at 1.
+struct JsonRecords<'a> {
+    input: Input<'a>,
+    line: u64,
+    column: u64,
+    state: JsonState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JsonState {
+    /// Before the document
+    Start,
+    /// Inside the top-level array, before an element
+    Array,
+    /// After the top-level array, where only whitespace may follow
+    End,
+    /// After the end of the document or an error that ends it
+    Done,
+}
+
+impl<'a> JsonRecords<'a> {
+    fn new(input: Input<'a>) -> Self {
+        Self {
+            input,
+            line: 1,
+            column: 1,
+            state: JsonState::Start,
+        }
+    }
+
+    fn location(&self) -> (u64, u64) {
+        (self.line, self.column)
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>> {
+        Ok(self.input.fill_buf()?.first().copied())
+    }
+
+    fn bump(&mut self, byte: u8) {
+        self.input.consume(1);
+        if byte == b'\n' {
+            self.line += 1;
+            self.column = 1;
+        } else if byte & 0xC0 != 0x80 {
+            // Count characters, not UTF-8 continuation bytes
+            self.column += 1;
+        }
+    }
+
+    /// Skip whitespace and return the next byte without consuming it
+    fn skip_whitespace(&mut self) -> Result<Option<u8>> {
+        while let Some(byte) = self.peek()? {
+            if !byte.is_ascii_whitespace() {
+                return Ok(Some(byte));
+            }
+            self.bump(byte);
+        }
+        Ok(None)
+    }
+
+    /// Bytes of the next array element, up to the `,` or `]` after it
+    fn element(&mut self) -> Result<Vec<u8>> {
+        let mut element = Vec::new();
+        let (mut depth, mut in_string, mut escaped) = (0usize, false, false);
+        while let Some(byte) = self.peek()? {
+            if in_string {
+                match byte {
+                    _ if escaped => escaped = false,
+                    b'\\' => escaped = true,
+                    b'"' => in_string = false,
+                    _ => {}
+                }
+            } else {
+                match byte {
+                    b',' | b']' | b'}' if depth == 0 => break,
+                    b'"' => in_string = true,
+                    b'[' | b'{' => depth += 1,
+                    b']' | b'}' => depth -= 1,
+                    _ => {}
+                }
+            }
+            element.push(byte);
+            self.bump(byte);
+        }
+        Ok(element)
+    }
+
+    /// Next record, or an error that ends the document
+    fn read(&mut self) -> Result<Option<Result<Record>>> {
+        let next = self.skip_whitespace()?;
+        let start = self.location();
+        match (self.state, next) {
+            (JsonState::Start | JsonState::End | JsonState::Done, None) => {
+                self.state = JsonState::Done;
+                Ok(None)
+            }
+            (JsonState::Array, None) => Err(parse_error(start, "expected a value")),
+            (JsonState::End | JsonState::Done, Some(_)) => {
+                Err(parse_error(start, "trailing characters"))
+            }
+            (JsonState::Start, Some(b'[')) => {
+                self.bump(b'[');
+                self.state = JsonState::Array;
+                if self.skip_whitespace()? == Some(b']') {
+                    self.bump(b']');
+                    self.state = JsonState::End;
+                }
+                self.read()
+            }
+            (JsonState::Start, Some(_)) => {
+                // A single value is one record
+                let mut document = Vec::new();
+                self.input.read_to_end(&mut document)?;
+                self.state = JsonState::Done;
+                let record = serde_json::from_slice(&document)
+                    .map_err(|e| json_error(start, &e))
+                    .and_then(|value| json_record(start, value));
+                Ok(Some(record))
+            }
+            (JsonState::Array, Some(_)) => {
+                let element = self.element()?;
+                if element.is_empty() {
+                    return Err(parse_error(start, "expected a value"));
+                }
+                let value = serde_json::from_slice(&element).map_err(|e| json_error(start, &e))?;
+                match self.peek()? {
+                    Some(b',') => self.bump(b','),
+                    Some(b']') => {
+                        self.bump(b']');
+                        self.state = JsonState::End;
+                    }
+                    _ => return Err(parse_error(self.location(), "expected `,` or `]`")),
+                }
+                Ok(Some(json_record(start, value)))
+            }
+        }
+    }
+}
+
+impl Iterator for JsonRecords<'_> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.state == JsonState::Done {
+            return None;
+        }
+        match self.read() {
+            Ok(record) => record,
+            Err(e) => {
+                self.state = JsonState::Done;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// JSON document holding one object or an array of objects
+///
+/// An array is read one element at a time, so only one record is held in
+/// memory; a single object is read whole.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+impl InputFormat for Json {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["json"]
+    }
+
+    fn sniff(&self, sample: &str) -> u8 {
+        if !sample.trim_start().starts_with(['{', '[']) {
+            return 0;
+        }
+        match serde_json::from_str::<IgnoredAny>(sample) {
+            Ok(_) => 80,
+            Err(e) if e.is_eof() => 80,
+            Err(_) => 0,
+        }
+    }
+
+    fn records<'a>(&self, input: Input<'a>) -> Records<'a> {
+        Box::new(JsonRecords::new(input))
+    }
+}
+
+/// One JSON object per line
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ndjson;
+
+impl InputFormat for Ndjson {
+    fn name(&self) -> &'static str {
+        "ndjson"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["ndjson", "jsonl"]
+    }
+
+    fn sniff(&self, sample: &str) -> u8 {
+        let lines: Vec<_> = sample
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .take(10)
+            .collect();
+        let objects = lines.iter().all(|line| {
+            serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(line).is_ok()
+        });
+        if lines.len() >= 2 && objects { 90 } else { 0 }
+    }
+
+    fn records<'a>(&self, input: Input<'a>) -> Records<'a> {
+        Box::new(Lines::new(input).filter_map(|line| match line {
+            Ok((_, text)) if text.trim().is_empty() => None,
+            Ok((line, text)) => {
+                // Positions are relative to the line; shift them to the input
+                let shift = |e: Error| match e {
+                    Error::Parse {
+                        column, message, ..
+                    } => Error::Parse {
+                        line,
+                        column,
+                        message,
+                    },
+                    e => e,
+                };
+                let record = serde_json::from_str(&text)
+                    .map_err(|e| json_error((1, 1), &e))
+                    .and_then(|value| json_record((1, 1), value))
+                    .map(|record| Record { line, ..record })
+                    .map_err(shift);
+                Some(record)
+            }
+            Err(e) => Some(Err(e)),
+        }))
+    }
+}
+
+enum RowError {
+    /// Quoted field starting at the offset is not closed on this line
+    Unterminated(usize),
+    /// Malformed row at the offset
+    Invalid(usize, &'static str),
+}
+
+/// Fields of one CSV row, which may span lines inside quoted fields
+fn split_row(text: &str) -> std::result::Result<Vec<String>, RowError> {
+    let mut fields = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    loop {
+        let mut field = String::new();
+        let last = if let Some(&(start, '"')) = chars.peek() {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some((_, '"')) if matches!(chars.peek(), Some((_, '"'))) => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    Some((_, '"')) => break,
+                    Some((_, c)) => field.push(c),
+                    None => return Err(RowError::Unterminated(start)),
+                }
+            }
+            match chars.next() {
+                None => true,
+                Some((_, ',')) => false,
+                Some((offset, _)) => {
+                    return Err(RowError::Invalid(offset, "expected `,` after quoted field"));
+                }
+            }
+        } else {
+            loop {
+                match chars.next() {
+                    None => break true,
+                    Some((_, ',')) => break false,
+                    Some((offset, '"')) => {
+                        return Err(RowError::Invalid(offset, "quote in unquoted field"));
+                    }
+                    Some((_, c)) => field.push(c),
+                }
+            }
+        };
+        fields.push(field);
+        if last {
+            return Ok(fields);
+        }
+    }
+}
+
+/// CSV rows with the line each starts on
+struct Rows<'a> {
+    lines: Lines<'a>,
+}
+
+impl Iterator for Rows<'_> {
+    type Item = Result<(u64, Vec<String>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (start, mut text) = match self.lines.next()? {
+            Ok(line) => line,
+            Err(e) => return Some(Err(e)),
+        };
+        loop {
+            let (offset, message) = match split_row(&text) {
+                Ok(fields) => return Some(Ok((start, fields))),
+                Err(RowError::Unterminated(offset)) => match self.lines.next() {
+                    Some(Ok((_, more))) => {
+                        text.push('\n');
+                        text.push_str(&more);
+                        continue;
+                    }
+                    Some(Err(e)) => return Some(Err(e)),
+                    None => (offset, "unterminated quoted field"),
+                },
+                Err(RowError::Invalid(offset, message)) => (offset, message),
+            };
+            let (line, column) = position(&text, offset);
+            return Some(Err(Error::Parse {
+                line: start + line - 1,
+                column,
+                message: message.to_string(),
+            }));
+        }
+    }
+}
+
+/// Comma-separated values with a header row naming the fields
+///
+/// Every value is a string. Quoted fields may contain commas, doubled quotes
+/// and line breaks.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Csv;
+
+impl InputFormat for Csv {
+    fn name(&self) -> &'static str {
+        "csv"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["csv"]
+    }
+
+    fn sniff(&self, sample: &str) -> u8 {
+        let rows = Rows {
+            lines: Lines::new(Box::new(sample.as_bytes())),
+        };
+        let mut widths = Vec::new();
+        for row in rows.take(10) {
+            match row {
+                Ok((_, fields)) if fields.len() == 1 && fields[0].is_empty() => {}
+                Ok((_, fields)) => widths.push(fields.len()),
+                Err(_) => return 0,
+            }
+        }
+        match widths.split_first() {
+            Some((&header, rest)) if header >= 2 => {
+                // Tolerate the odd malformed row, which parsing reports later
+                let matching = rest.iter().filter(|&&width| width == header).count();
+                if rest.is_empty() {
+                    30
+                } else if matching == rest.len() {
+                    60
+                } else if matching * 2 > rest.len() {
+                    40
+                } else {
+                    0
+                }
+            }
+            _ => 0,
+        }
+    }
+
+    fn records<'a>(&self, input: Input<'a>) -> Records<'a> {
+        let mut header: Option<Vec<String>> = None;
+        let rows = Rows {
+            lines: Lines::new(input),
+        };
+        Box::new(rows.filter_map(move |row| {
+            let (line, fields) = match row {
+                Ok((_, fields)) if fields.len() == 1 && fields[0].is_empty() => return None,
+                Ok(row) => row,
+                Err(e) => return Some(Err(e)),
+            };
+            let Some(names) = &header else {
+                header = Some(fields);
+                return None;
+            };
+            if fields.len() != names.len() {
+                return Some(Err(Error::Parse {
+                    line,
+                    column: 1,
+                    message: format!("expected {} fields, found {}", names.len(), fields.len()),
+                }));
+            }
+            let fields = names
+                .iter()
+                .cloned()
+                .zip(fields.into_iter().map(Value::String))
+                .collect();
+            Some(Ok(Record { line, fields }))
+        }))
+    }
+}
+
+/// Plain text, one record per non-blank line
+///
+/// The line is the only field of its record, [`LINE_FIELD`], and is written
+/// back out as-is. Never detected from the content; it is what [`detect`]
+/// falls back to.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Text;
+
+/// Field holding the line of a [`Text`] record
+pub const LINE_FIELD: &str = "line";
+
+impl InputFormat for Text {
+    fn name(&self) -> &'static str {
+        "text"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["txt", "text", "log"]
+    }
+
+    fn sniff(&self, _sample: &str) -> u8 {
+        0
+    }
+
+    fn records<'a>(&self, input: Input<'a>) -> Records<'a> {
+        Box::new(Lines::new(input).filter_map(|line| match line {
+            Ok((_, text)) if text.trim().is_empty() => None,
+            Ok((line, text)) => {
+                let mut record = Record::new(line);
+                record
+                    .fields
+                    .insert(LINE_FIELD.to_string(), Value::String(text));
+                Some(Ok(record))
+            }
+            Err(e) => Some(Err(e)),
+        }))
+    }
+
+    fn output_format(&self) -> OutputFormat {
+        OutputFormat::Text
+    }
+}
+
+fn toml_value(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::Integer(i),
+        toml::Value::Float(f) => Value::Float(f),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(datetime) => Value::String(datetime.to_string()),
+        toml::Value::Array(values) => Value::List(values.into_iter().map(toml_value).collect()),
+        toml::Value::Table(table) => Value::Map(toml_fields(table)),
+    }
+}
+
+fn toml_fields(table: toml::Table) -> BTreeMap<String, Value> {
+    table
+        .into_iter()
+        .map(|(key, value)| (key, toml_value(value)))
+        .collect()
+}
+
+/// TOML document; an array of tables, e.g. `[[records]]`, is one record per table
+///
+/// The document is read whole before any record is produced.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Toml;
+
+impl Toml {
+    fn parse(text: &str) -> Vec<Result<Record>> {
+        let table: toml::Table = match toml::from_str(text) {
+            Ok(table) => table,
+            Err(e) => {
+                let offset = e.span().map_or(0, |span| span.start);
+                return vec![Err(error_at(text, offset, e.message().trim_end()))];
+            }
+        };
+
+        let mut entries = table.into_iter();
+        let (key, values) = match (entries.next(), entries.next()) {
+            (Some((key, toml::Value::Array(values))), None)
+                if values.iter().all(toml::Value::is_table) =>
+            {
+                (key, values)
+            }
+            (first, second) => {
+                let fields = first.into_iter().chain(second).chain(entries).collect();
+                return vec![Ok(Record {
+                    line: 1,
+                    fields: toml_fields(fields),
+                })];
+            }
+        };
+
+        // Records start at their `[[key]]` headers, if written that way
+        let header = format!("[[{key}]]");
+        let mut starts = text
+            .lines()
+            .zip(1..)
+            .filter(|(line, _)| line.trim().replace(' ', "") == header)
+            .map(|(_, number)| number);
+        values
+            .into_iter()
+            .filter_map(|value| match value {
+                toml::Value::Table(table) => Some(Ok(Record {
+                    line: starts.next().unwrap_or(1),
+                    fields: toml_fields(table),
+                })),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl InputFormat for Toml {
+    fn name(&self) -> &'static str {
+        "toml"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["toml"]
+    }
+
+    fn sniff(&self, sample: &str) -> u8 {
+        let first = sample
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'));
+        let looks_like_toml = first.is_some_and(|line| {
+            let table = line.starts_with('[') && line.ends_with(']');
+            let key = line.split_once('=').is_some_and(|(key, _)| {
+                let key = key.trim();
+                !key.is_empty()
+                    && key.chars().all(|c| {
+                        c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '"' | '\'' | ' ')
+                    })
+            });
+            table || key
+        });
+        match (
+            looks_like_toml,
+            toml::from_str::<toml::Table>(sample).is_ok(),
+        ) {
+            (true, true) => 70,
+            (true, false) => 40,
+            (false, _) => 0,
+        }
+    }
+
+    fn records<'a>(&self, input: Input<'a>) -> Records<'a> {
+        document_records(input, Self::parse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(format: &dyn InputFormat, input: &'static str) -> Vec<Result<Record>> {
+        format.records(Box::new(input.as_bytes())).collect()
+    }
+
+    fn position_of(result: &Result<Record>) -> (u64, u64) {
+        match result {
+            Err(Error::Parse { line, column, .. }) => (*line, *column),
+            other => panic!("expected parse error, got {other:?}"),
+        }
+    }
+
+    fn detected(path: Option<&str>, input: &'static str) -> &'static str {
+        let (format, mut input) = detect(path.map(Path::new), Box::new(input.as_bytes())).unwrap();
+        // Detection must not consume the input
+        let mut rest = String::new();
+        input.read_to_string(&mut rest).unwrap();
+        assert!(!rest.is_empty());
+        format.name()
+    }
+
+    #[test]
+    fn test_detection() {
+        assert_eq!(detected(Some("data.JSONL"), "a,b\n1,2\n"), "ndjson");
+        assert_eq!(detected(None, "[{\"a\": 1},\n {\"a\": 2}]"), "json");
+        assert_eq!(detected(None, "{\"a\": 1}\n{\"a\": 2}\n"), "ndjson");
+        assert_eq!(detected(None, "name,count\nx,1\n\"y, z\",2\nw\n"), "csv");
+        assert_eq!(detected(None, "# items\n[[items]]\nname = \"x\"\n"), "toml");
+        assert_eq!(detected(None, "title = \"x\"\n"), "toml");
+
+        assert_eq!(detected(None, "plain text\nmore, text\n"), "text");
+        assert_eq!(
+            detected(Some("notes.txt"), "{\"a\": 1}\n{\"a\": 2}\n"),
+            "text"
+        );
+        assert_eq!(by_name("CSV").unwrap().name(), "csv");
+        assert!(matches!(by_name("xml"), Err(Error::Usage(_))));
+    }
+
+    #[test]
+    fn test_json_records_and_errors() {
+        let records = parse(
+            &Json,
+            "[\n  {\"id\": 1, \"tags\": [\"a\"]},\n  2,\n  {\"id\": 1.5}\n]",
+        );
+        let first = records[0].as_ref().unwrap();
+        assert_eq!(first.line, 2);
+        assert_eq!(first.get("id"), Some(&Value::Integer(1)));
+        assert_eq!(
+            first.get("tags"),
+            Some(&Value::List(vec![Value::String("a".to_string())]))
+        );
+        assert_eq!(position_of(&records[1]), (3, 3));
+        assert_eq!(
+            records[2].as_ref().unwrap().get("id"),
+            Some(&Value::Float(1.5))
+        );
+
+        let records = parse(&Json, "[{\"id\": 1},\n {\"id\": }]");
+        assert_eq!(records.len(), 2);
+        assert_eq!(position_of(&records[1]), (2, 9));
+        assert_eq!(parse(&Json, "{\"id\": 1}").len(), 1);
+        assert!(parse(&Json, "  ").is_empty());
+        assert!(parse(&Json, "[ ]").is_empty());
+
+        // Truncated at the start of a line, where serde_json reports column 0
+        let records = parse(&Json, "[{\"id\": 1},\n {\"id\": 2,\n");
+        assert_eq!(position_of(&records[1]), (3, 1));
+        let records = parse(&Json, "[{\"id\": 1},\n");
+        assert_eq!(position_of(&records[1]), (2, 1));
+        let records = parse(&Json, "[{\"s\": \"a,]\\\"}\"}] x");
+        assert_eq!(
+            records[0].as_ref().unwrap().get("s"),
+            Some(&Value::String("a,]\"}".to_string()))
+        );
+        assert_eq!(position_of(&records[1]), (1, 19));
+
+        // Elements are parsed as they arrive, before the rest is read
+        struct Broken;
+        impl Read for Broken {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("disk"))
+            }
+        }
+        let input = std::io::BufReader::new(&b"[{\"id\": 1},"[..]).chain(Broken);
+        let mut records = Json.records(Box::new(std::io::BufReader::new(input)));
+        assert!(records.next().unwrap().is_ok());
+        assert!(matches!(records.next(), Some(Err(Error::Io(_)))));
+    }
+
+    #[test]
+    fn test_ndjson_resumes_after_bad_lines() {
+        let records = parse(
+            &Ndjson,
+            "{\"a\": 1}\n\n{\"a\": tru}\r\n[1]\n{\"a\": null}\n",
+        );
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0].as_ref().unwrap().line, 1);
+        assert_eq!(position_of(&records[1]), (3, 10));
+        assert_eq!(position_of(&records[2]), (4, 1));
+        let last = records[3].as_ref().unwrap();
+        assert_eq!((last.line, last.get("a")), (5, Some(&Value::Null)));
+    }
+
+    #[test]
+    fn test_csv_quoting_and_errors() {
+        let input = "name,note\nx,\"a, \"\"b\"\"\"\ny,\"two\nlines\"\nz\nw,bad\"quote\nv,\"open";
+        let records = parse(&Csv, input);
+        assert_eq!(records.len(), 5);
+        let first = records[0].as_ref().unwrap();
+        assert_eq!(
+            first.get("note"),
+            Some(&Value::String("a, \"b\"".to_string()))
+        );
+        let second = records[1].as_ref().unwrap();
+        assert_eq!(second.line, 3);
+        assert_eq!(
+            second.get("note"),
+            Some(&Value::String("two\nlines".to_string()))
+        );
+        assert_eq!(position_of(&records[2]), (5, 1));
+        assert_eq!(position_of(&records[3]), (6, 6));
+        assert_eq!(position_of(&records[4]), (7, 3));
+    }
+
+    #[test]
+    fn test_text_records() {
+        let records = parse(&Text, "a\n\n  \r\nb c\r\n");
+        let lines: Vec<_> = records
+            .iter()
+            .map(|r| {
+                let record = r.as_ref().unwrap();
+                (record.line, record.get(LINE_FIELD).cloned())
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                (1, Some(Value::String("a".to_string()))),
+                (4, Some(Value::String("b c".to_string())))
+            ]
+        );
+        assert_eq!(Text.output_format(), OutputFormat::Text);
+        assert_eq!(Csv.output_format(), OutputFormat::JsonLines);
+    }
+
+    #[test]
+    fn test_toml_records_and_errors() {
+        let input = "[[servers]]\nname = \"a\"\nstarted = 2024-01-02T03:04:05Z\n\n[[ servers ]]\nname = \"b\"\n";
+        let records = parse(&Toml, input);
+        let lines: Vec<_> = records.iter().map(|r| r.as_ref().unwrap().line).collect();
+        assert_eq!(lines, [1, 5]);
+        assert_eq!(
+            records[0].as_ref().unwrap().get("started"),
+            Some(&Value::String("2024-01-02T03:04:05Z".to_string()))
+        );
+
+        let single = parse(&Toml, "name = \"x\"\n[limits]\nmax = 3\n");
+        assert_eq!(single.len(), 1);
+        assert!(matches!(
+            single[0].as_ref().unwrap().get("limits"),
+            Some(Value::Map(_))
+        ));
+
+        let broken = parse(&Toml, "name = \"x\"\ncount = \n");
+        assert_eq!(position_of(&broken[0]).0, 2);
+    }
+}
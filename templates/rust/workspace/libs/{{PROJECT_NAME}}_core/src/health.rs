@@ -0,0 +1,297 @@
+//! Health checks and readiness reports
+//!
+//! Subsystems register named [`HealthCheck`]s on the [`HealthRegistry`] of
+//! [`AppState`]. Running them produces a [`HealthReport`] whose overall status
+//! is the worst individual status, with an exit code scripts can gate on.
+
+use std::fmt;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::snapshot::SnapshotStore;
+use crate::types::AppState;
+
+/// Exit code when at least one check is degraded (`EX_TEMPFAIL`)
+pub const EXIT_DEGRADED: u8 = 75;
+/// Exit code when at least one check is unhealthy (`EX_UNAVAILABLE`)
+pub const EXIT_UNHEALTHY: u8 = 69;
+
+/// Outcome of a health check, ordered from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HealthStatus {
+    /// Working as expected
+    Healthy,
+    /// Working, but with reduced functionality
+    Degraded,
+    /// Not working
+    Unhealthy,
+}
+
+impl HealthStatus {
+    /// Process exit code for this status
+    pub const fn exit_code(self) -> u8 {
+        match self {
+            Self::Healthy => 0,
+            Self::Degraded => EXIT_DEGRADED,
+            Self::Unhealthy => EXIT_UNHEALTHY,
+        }
+    }
+}
+
+impl fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Healthy => "healthy",
+            Self::Degraded => "degraded",
+            Self::Unhealthy => "unhealthy",
+        })
+    }
+}
+
+/// Status of a check with human-readable details
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckResult {
+    /// Overall status of the check
+    pub status: HealthStatus,
+    /// What was checked or what is wrong
+    pub details: String,
+}
+
+impl CheckResult {
+    /// Healthy result
+    pub fn healthy(details: impl Into<String>) -> Self {
+        Self {
+            status: HealthStatus::Healthy,
+            details: details.into(),
+        }
+    }
+
+    /// Degraded result
+    pub fn degraded(details: impl Into<String>) -> Self {
+        Self {
+            status: HealthStatus::Degraded,
+            details: details.into(),
+        }
+    }
+
+    /// Unhealthy result
+    pub fn unhealthy(details: impl Into<String>) -> Self {
+        Self {
+            status: HealthStatus::Unhealthy,
+            details: details.into(),
+        }
+    }
+}
+
+/// Named check registered by a subsystem
+pub trait HealthCheck: Send + Sync {
+    /// Inspect the subsystem
+    fn check(&self, state: &AppState) -> CheckResult;
+}
+
+impl<F> HealthCheck for F
+where
+    F: Fn(&AppState) -> CheckResult + Send + Sync,
+{
+    fn check(&self, state: &AppState) -> CheckResult {
+        self(state)
+    }
+}
+
+/// Result of one check in a [`HealthReport`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckOutcome {
+    /// Name the check was registered with
+    pub name: String,
+    /// What the check reported
+    pub result: CheckResult,
+    /// How long the check took
+    pub duration: Duration,
+}
+
+/// Results of every registered check
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HealthReport {
+    /// Results in registration order
+    pub checks: Vec<CheckOutcome>,
+}
+
+impl HealthReport {
+    /// Worst status of all checks; healthy if there are none
+    pub fn status(&self) -> HealthStatus {
+        self.checks
+            .iter()
+            .map(|outcome| outcome.result.status)
+            .max()
+            .unwrap_or(HealthStatus::Healthy)
+    }
+
+    /// Process exit code for the overall status
+    pub fn exit_code(&self) -> u8 {
+        self.status().exit_code()
+    }
+}
+
+impl fmt::Display for HealthReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.status())?;
+        for outcome in &self.checks {
+            write!(
+                f,
+                "\n  [{}] {}: {}",
+                outcome.result.status, outcome.name, outcome.result.details
+            )?;
+        }
+        Ok(())
+    }
+}
+
+type Checks = Vec<(String, Arc<dyn HealthCheck>)>;
+
+/// Cloneable registry of named health checks
+#[derive(Clone, Default)]
+pub struct HealthRegistry {
+    checks: Arc<Mutex<Checks>>,
+}
+
+impl fmt::Debug for HealthRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.checks().iter().map(|(name, _)| name))
+            .finish()
+    }
+}
+
+impl HealthRegistry {
+    /// Create a registry without checks
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a registry with the `config`, `data_dir` and `plugins` checks
+    pub fn with_builtin_checks() -> Self {
+        let registry = Self::new();
+        registry.register("config", check_config);
+        registry.register("data_dir", check_data_dir);
+        registry.register("plugins", |_: &AppState| crate::core::plugin_health());
+        registry
+    }
+
+    fn checks(&self) -> MutexGuard<'_, Checks> {
+        self.checks.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Register `check` under `name`, replacing a check with the same name
+    pub fn register(&self, name: impl Into<String>, check: impl HealthCheck + 'static) {
+        let name = name.into();
+        let check: Arc<dyn HealthCheck> = Arc::new(check);
+        let mut checks = self.checks();
+        match checks.iter_mut().find(|(existing, _)| *existing == name) {
+            Some((_, existing)) => *existing = check,
+            None => checks.push((name, check)),
+        }
+    }
+
+    /// Run every check against `state`; a panicking check counts as unhealthy
+    pub fn run(&self, state: &AppState) -> HealthReport {
+        // Run outside the lock so checks can inspect the registry
+        let checks = self.checks().clone();
+        let checks = checks
+            .into_iter()
+            .map(|(name, check)| {
+                let started = Instant::now();
+                let result = panic::catch_unwind(AssertUnwindSafe(|| check.check(state)))
+                    .unwrap_or_else(|_| CheckResult::unhealthy("check panicked"));
+                CheckOutcome {
+                    name,
+                    result,
+                    duration: started.elapsed(),
+                }
+            })
+            .collect();
+        HealthReport { checks }
+    }
+}
+
+/// The configuration in effect passes validation
+fn check_config(state: &AppState) -> CheckResult {
+    match state.config().validate() {
+        Ok(()) => CheckResult::healthy("valid"),
+        Err(e) => CheckResult::unhealthy(e.to_string()),
+    }
+}
+
+/// The directory for state snapshots exists or can be created, and is writable
+fn check_data_dir(state: &AppState) -> CheckResult {
+    let store = match SnapshotStore::for_config(&state.config()) {
+        Ok(store) => store,
+        Err(e) => return CheckResult::unhealthy(e.to_string()),
+    };
+    let Some(dir) = store.path().parent() else {
+        return CheckResult::unhealthy("snapshot path has no parent directory");
+    };
+    let probe = dir.join(".health-probe");
+    let written = fs::create_dir_all(dir).and_then(|()| fs::write(&probe, b"ok"));
+    let _ = fs::remove_file(&probe);
+    match written {
+        Ok(()) => CheckResult::healthy(format!("{} is writable", dir.display())),
+        Err(e) => CheckResult::unhealthy(format!("{} is not writable: {e}", dir.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Config;
+
+    #[test]
+    fn test_report_takes_worst_status() {
+        let state = AppState::new(Config::default());
+        let health = HealthRegistry::new();
+        assert_eq!(health.run(&state).exit_code(), 0);
+
+        health.register("cache", |_: &AppState| CheckResult::healthy("warm"));
+        health.register("queue", |_: &AppState| CheckResult::degraded("backlog"));
+        let report = health.run(&state);
+        assert_eq!(report.status(), HealthStatus::Degraded);
+        assert_eq!(report.exit_code(), EXIT_DEGRADED);
+        assert_eq!(
+            report.to_string(),
+            "degraded\n  [healthy] cache: warm\n  [degraded] queue: backlog"
+        );
+
+        health.register("queue", |_: &AppState| -> CheckResult { panic!("boom") });
+        let report = health.run(&state);
+        assert_eq!(report.checks.len(), 2);
+        assert_eq!(
+            report.checks[1].result,
+            CheckResult::unhealthy("check panicked")
+        );
+        assert_eq!(report.exit_code(), EXIT_UNHEALTHY);
+    }
+
+    #[test]
+    fn test_builtin_checks() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState::new(Config {
+            data_dir: Some(dir.path().join("data").display().to_string()),
+            ..Config::default()
+        });
+        let report = HealthRegistry::with_builtin_checks().run(&state);
+        let names: Vec<_> = report.checks.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["config", "data_dir", "plugins"]);
+        assert_eq!(report.checks[0].result.status, HealthStatus::Healthy);
+        assert_eq!(report.checks[1].result.status, HealthStatus::Healthy);
+        assert!(!dir.path().join("data/.health-probe").exists());
+
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        let state = AppState::new(Config {
+            data_dir: Some(file.join("data").display().to_string()),
+            ..Config::default()
+        });
+        assert_eq!(check_data_dir(&state).status, HealthStatus::Unhealthy);
+    }
+}
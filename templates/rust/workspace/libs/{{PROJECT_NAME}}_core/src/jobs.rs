@@ -0,0 +1,563 @@
+//! In-process job queue with priorities, retries and a dead-letter list
+//!
+//! Jobs run on a pool of worker threads sized by `Config::max_concurrent`.
+//! Each attempt is registered as an operation on the shared [`AppState`], so
+//! it holds a concurrency permit, shows up in the operation registry and is
+//! cancelled on shutdown. Jobs that exhaust their attempts or panic are moved
+//! to the dead-letter list for inspection.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::operations::CancellationToken;
+use crate::types::AppState;
+use crate::{Error, Result};
+
+/// Unit of work that can be retried
+pub trait Job: Send + 'static {
+    /// Name shown in the operation registry and dead-letter list
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Run one attempt; an error schedules a retry until attempts are exhausted
+    ///
+    /// A panic dead-letters the job without retrying it.
+    fn run(&mut self, ctx: &JobContext) -> Result<()>;
+}
+
+/// Information passed to a running job attempt
+#[derive(Debug, Clone)]
+pub struct JobContext {
+    /// Identifier of the job
+    pub id: JobId,
+    /// Attempt number, starting at 1
+    pub attempt: u32,
+    /// Cancelled when the job's operation is cancelled, e.g. on shutdown
+    pub token: CancellationToken,
+}
+
+/// Unique identifier of a submitted job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct JobId(u64);
+
+impl fmt::Display for JobId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "job-{}", self.0)
+    }
+}
+
+/// Delay between failed attempts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backoff {
+    /// Retry immediately
+    None,
+    /// Wait the same time before every retry
+    Fixed(Duration),
+    /// Double the wait after every failure, up to `max`
+    Exponential {
+        /// Wait before the first retry
+        initial: Duration,
+        /// Upper bound for the wait
+        max: Duration,
+    },
+}
+
+impl Backoff {
+    /// Wait before retrying after `attempt` failed attempts
+    pub fn delay(&self, attempt: u32) -> Duration {
+        match *self {
+            Self::None => Duration::ZERO,
+            Self::Fixed(delay) => delay,
+            Self::Exponential { initial, max } => {
+                let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+                initial.saturating_mul(factor).min(max)
+            }
+        }
+    }
+}
+
+/// Scheduling options of a submitted job
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobOptions {
+    /// Higher priorities run first; equal priorities run in submission order
+    pub priority: i32,
+    /// Total number of attempts before the job is dead-lettered
+    pub max_attempts: u32,
+    /// Delay between attempts
+    pub backoff: Backoff,
+}
+
+impl Default for JobOptions {
+    fn default() -> Self {
+        Self {
+            priority: 0,
+            max_attempts: 3,
+            backoff: Backoff::Exponential {
+                initial: Duration::from_millis(100),
+                max: Duration::from_secs(10),
+            },
+        }
+    }
+}
+
+/// Job that failed all its attempts
+#[derive(Debug)]
+pub struct DeadLetter<J> {
+    /// Identifier of the job
+    pub id: JobId,
+    /// The job itself, so it can be inspected or resubmitted
+    pub job: J,
+    /// Number of attempts made
+    pub attempts: u32,
+    /// Error of the last attempt
+    pub error: String,
+    /// When the job was dead-lettered
+    pub failed_at: SystemTime,
+}
+
+/// Counters describing the queue
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JobStats {
+    /// Jobs waiting to run, including those waiting for a retry
+    pub queued: usize,
+    /// Jobs currently running
+    pub running: usize,
+    /// Jobs that completed successfully
+    pub succeeded: usize,
+    /// Jobs moved to the dead-letter list
+    pub dead: usize,
+}
+
+struct Pending<J> {
+    id: JobId,
+    job: J,
+    options: JobOptions,
+    attempts: u32,
+    ready_at: Instant,
+}
+
+impl<J> PartialEq for Pending<J> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<J> Eq for Pending<J> {}
+
+impl<J> PartialOrd for Pending<J> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<J> Ord for Pending<J> {
+    /// Highest priority first, then oldest submission
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.options
+            .priority
+            .cmp(&other.options.priority)
+            .then(other.id.cmp(&self.id))
+    }
+}
+
+/// Far enough ahead that a retry scheduled then never runs in practice
+const FAR_FUTURE: Duration = Duration::from_secs(30 * 365 * 24 * 60 * 60);
+
+/// When a retry waiting `delay` is due, saturating instead of overflowing
+fn retry_at(delay: Duration) -> Instant {
+    let now = Instant::now();
+    now.checked_add(delay).unwrap_or_else(|| now + FAR_FUTURE)
+}
+
+struct Queue<J> {
+    pending: BinaryHeap<Pending<J>>,
+    /// Jobs waiting for their retry backoff to elapse
+    delayed: Vec<Pending<J>>,
+    dead: Vec<DeadLetter<J>>,
+    next_id: u64,
+    running: usize,
+    succeeded: usize,
+    closed: bool,
+}
+
+struct Inner<J> {
+    state: Arc<AppState>,
+    queue: Mutex<Queue<J>>,
+    changed: Condvar,
+}
+
+impl<J: Job> Inner<J> {
+    fn lock(&self) -> MutexGuard<'_, Queue<J>> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait<'a>(
+        &self,
+        guard: MutexGuard<'a, Queue<J>>,
+        timeout: Option<Duration>,
+    ) -> MutexGuard<'a, Queue<J>> {
+        match timeout {
+            Some(timeout) => {
+                self.changed
+                    .wait_timeout(guard, timeout)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0
+            }
+            None => self
+                .changed
+                .wait(guard)
+                .unwrap_or_else(PoisonError::into_inner),
+        }
+    }
+
+    /// Take the next ready job, or `None` once the queue is closed and drained
+    fn next(&self) -> Option<Pending<J>> {
+        let mut queue = self.lock();
+        loop {
+            let now = Instant::now();
+            let (ready, delayed) = std::mem::take(&mut queue.delayed)
+                .into_iter()
+                .partition::<Vec<_>, _>(|pending| pending.ready_at <= now);
+            queue.pending.extend(ready);
+            queue.delayed = delayed;
+
+            if let Some(next) = queue.pending.pop() {
+                queue.running += 1;
+                return Some(next);
+            }
+            let timeout = queue
+                .delayed
+                .iter()
+                .map(|pending| pending.ready_at - now)
+                .min();
+            if timeout.is_none() && queue.closed {
+                return None;
+            }
+            queue = self.wait(queue, timeout);
+        }
+    }
+
+    fn work(&self) {
+        while let Some(mut pending) = self.next() {
+            pending.attempts += 1;
+            let operation = self.state.start_operation(pending.job.name().to_string());
+            let ctx = JobContext {
+                id: pending.id,
+                attempt: pending.attempts,
+                token: operation.token().clone(),
+            };
+            // The job may be left inconsistent by a panic, so it is not retried
+            let (result, retry) =
+                match panic::catch_unwind(AssertUnwindSafe(|| pending.job.run(&ctx))) {
+                    Ok(result) => (result, true),
+                    Err(payload) => {
+                        let message = payload
+                            .downcast_ref::<&str>()
+                            .copied()
+                            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                            .unwrap_or("unknown cause");
+                        (Err(Error::Other(format!("panicked: {message}"))), false)
+                    }
+                };
+            drop(operation);
+
+            let mut queue = self.lock();
+            queue.running -= 1;
+            match result {
+                Ok(()) => queue.succeeded += 1,
+                Err(e)
+                    if retry
+                        && pending.attempts < pending.options.max_attempts
+                        && !ctx.token.is_cancelled() =>
+                {
+                    tracing::warn!(
+                        job = %pending.id,
+                        attempt = pending.attempts,
+                        error = %e,
+                        "job failed, retrying"
+                    );
+                    pending.ready_at = retry_at(pending.options.backoff.delay(pending.attempts));
+                    queue.delayed.push(pending);
+                }
+                Err(e) => {
+                    tracing::error!(
+                        job = %pending.id,
+                        attempts = pending.attempts,
+                        error = %e,
+                        "job dead-lettered"
+                    );
+                    queue.dead.push(DeadLetter {
+                        id: pending.id,
+                        job: pending.job,
+                        attempts: pending.attempts,
+                        error: e.to_string(),
+                        failed_at: SystemTime::now(),
+                    });
+                }
+            }
+            self.changed.notify_all();
+        }
+    }
+}
+
+/// Priority job queue processed by a pool of worker threads
+pub struct JobQueue<J: Job> {
+    inner: Arc<Inner<J>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<J: Job> fmt::Debug for JobQueue<J> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JobQueue")
+            .field("workers", &self.workers.len())
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl<J: Job> JobQueue<J> {
+    /// Start `Config::max_concurrent` workers running jobs as operations on `state`
+    pub fn new(state: Arc<AppState>) -> Result<Self> {
+        let workers = state.config().max_concurrent;
+        let inner = Arc::new(Inner {
+            state,
+            queue: Mutex::new(Queue {
+                pending: BinaryHeap::new(),
+                delayed: Vec::new(),
+                dead: Vec::new(),
+                next_id: 0,
+                running: 0,
+                succeeded: 0,
+                closed: false,
+            }),
+            changed: Condvar::new(),
+        });
+        let workers = (0..workers)
+            .map(|index| {
+                let inner = Arc::clone(&inner);
+                thread::Builder::new()
+                    .name(format!("job-worker-{index}"))
+                    .spawn(move || inner.work())
+            })
+            .collect::<std::io::Result<_>>()?;
+        Ok(Self { inner, workers })
+    }
+
+    /// Queue a job for execution
+    pub fn submit(&self, job: J, options: JobOptions) -> JobId {
+        let mut queue = self.inner.lock();
+        queue.next_id += 1;
+        let id = JobId(queue.next_id);
+        queue.pending.push(Pending {
+            id,
+            job,
+            options,
+            attempts: 0,
+            ready_at: Instant::now(),
+        });
+        self.inner.changed.notify_all();
+        id
+    }
+
+    /// Current queue counters
+    pub fn stats(&self) -> JobStats {
+        let queue = self.inner.lock();
+        JobStats {
+            queued: queue.pending.len() + queue.delayed.len(),
+            running: queue.running,
+            succeeded: queue.succeeded,
+            dead: queue.dead.len(),
+        }
+    }
+
+    /// Inspect dead-lettered jobs without removing them
+    pub fn inspect_dead_letters<R>(&self, f: impl FnOnce(&[DeadLetter<J>]) -> R) -> R {
+        f(&self.inner.lock().dead)
+    }
+
+    /// Remove and return all dead-lettered jobs
+    pub fn take_dead_letters(&self) -> Vec<DeadLetter<J>> {
+        std::mem::take(&mut self.inner.lock().dead)
+    }
+
+    /// Block until no job is queued or running
+    pub fn wait_idle(&self) {
+        let mut queue = self.inner.lock();
+        while !queue.pending.is_empty() || !queue.delayed.is_empty() || queue.running > 0 {
+            queue = self.inner.wait(queue, None);
+        }
+    }
+
+    /// Stop accepting work, let the workers drain the queue and wait for them
+    pub fn close(mut self) -> JobStats {
+        self.stop_workers();
+        self.stats()
+    }
+
+    fn stop_workers(&mut self) {
+        self.inner.lock().closed = true;
+        self.inner.changed.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl<J: Job> Drop for JobQueue<J> {
+    fn drop(&mut self) {
+        self.stop_workers();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+    use crate::types::Config;
+
+    struct Record {
+        label: &'static str,
+        failures: u32,
+        panics: bool,
+        log: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl Job for Record {
+        fn name(&self) -> &str {
+            self.label
+        }
+
+        fn run(&mut self, _ctx: &JobContext) -> Result<()> {
+            if self.panics {
+                panic!("{} exploded", self.label);
+            }
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(Error::Other(format!("{} failed", self.label)));
+            }
+            self.log.lock().unwrap().push(self.label);
+            Ok(())
+        }
+    }
+
+    fn record(label: &'static str, failures: u32, log: &Arc<Mutex<Vec<&'static str>>>) -> Record {
+        Record {
+            label,
+            failures,
+            panics: false,
+            log: Arc::clone(log),
+        }
+    }
+
+    fn state(max_concurrent: usize) -> Arc<AppState> {
+        Arc::new(AppState::new(Config {
+            max_concurrent,
+            ..Config::default()
+        }))
+    }
+
+    fn options(priority: i32, max_attempts: u32) -> JobOptions {
+        JobOptions {
+            priority,
+            max_attempts,
+            backoff: Backoff::None,
+        }
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        let backoff = Backoff::Exponential {
+            initial: Duration::from_millis(100),
+            max: Duration::from_millis(300),
+        };
+        assert_eq!(backoff.delay(1), Duration::from_millis(100));
+        assert_eq!(backoff.delay(2), Duration::from_millis(200));
+        assert_eq!(backoff.delay(3), Duration::from_millis(300));
+        assert_eq!(
+            Backoff::Fixed(Duration::from_secs(1)).delay(7),
+            Duration::from_secs(1)
+        );
+
+        let unbounded = Backoff::Exponential {
+            initial: Duration::from_secs(u64::MAX / 2),
+            max: Duration::MAX,
+        };
+        assert_eq!(unbounded.delay(3), Duration::MAX);
+        let later = Instant::now() + Duration::from_secs(3600);
+        assert!(retry_at(unbounded.delay(3)) > later);
+        assert!(retry_at(Backoff::Fixed(Duration::MAX).delay(1)) > later);
+    }
+
+    #[test]
+    fn test_priority_order_with_single_worker() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let state = state(1);
+        let queue = JobQueue::new(Arc::clone(&state)).unwrap();
+
+        // Hold the only permit so the worker blocks while everything is queued
+        let permit = state.acquire();
+        queue.submit(record("first", 0, &log), options(0, 1));
+        while queue.stats().running == 0 {
+            thread::yield_now();
+        }
+        for (label, priority) in [("low", 0), ("high", 10), ("mid", 5), ("low2", 0)] {
+            queue.submit(record(label, 0, &log), options(priority, 1));
+        }
+        drop(permit);
+        queue.wait_idle();
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["first", "high", "mid", "low", "low2"]
+        );
+    }
+
+    #[test]
+    fn test_retries_then_dead_letters() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let queue = JobQueue::new(state(2)).unwrap();
+        queue.submit(record("flaky", 2, &log), options(0, 3));
+        let broken_id = queue.submit(record("broken", 5, &log), options(0, 2));
+        queue.wait_idle();
+
+        let stats = queue.stats();
+        assert_eq!(stats.succeeded, 1);
+        assert_eq!(stats.dead, 1);
+        queue.inspect_dead_letters(|dead| {
+            assert_eq!(dead[0].id, broken_id);
+            assert_eq!(dead[0].attempts, 2);
+            assert_eq!(dead[0].error, "broken failed");
+        });
+        assert_eq!(queue.take_dead_letters()[0].job.label, "broken");
+        assert_eq!(queue.close().dead, 0);
+        assert_eq!(*log.lock().unwrap(), ["flaky"]);
+    }
+
+    #[test]
+    fn test_panicking_job_is_dead_lettered() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let queue = JobQueue::new(state(1)).unwrap();
+        let id = queue.submit(
+            Record {
+                panics: true,
+                ..record("boom", 0, &log)
+            },
+            options(0, 3),
+        );
+        queue.submit(record("after", 0, &log), options(0, 1));
+        queue.wait_idle();
+
+        let stats = queue.stats();
+        assert_eq!((stats.running, stats.succeeded, stats.dead), (0, 1, 1));
+        let dead = queue.take_dead_letters();
+        assert_eq!((dead[0].id, dead[0].attempts), (id, 1));
+        assert_eq!(dead[0].error, "panicked: boom exploded");
+        assert_eq!(*log.lock().unwrap(), ["after"]);
+    }
+}
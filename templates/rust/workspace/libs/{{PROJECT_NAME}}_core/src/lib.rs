@@ -7,20 +7,97 @@
 #![warn(missing_docs)]
 #![warn(clippy::all)]
 
+extern crate alloc;
+
 pub mod error;
 pub mod types;
 
+#[cfg(feature = "async")]
+pub mod app;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod events;
+#[cfg(feature = "std")]
+pub mod flags;
+#[cfg(feature = "std")]
+pub mod formats;
+#[cfg(feature = "std")]
+pub mod health;
+#[cfg(feature = "std")]
+pub mod jobs;
+#[cfg(feature = "std")]
+pub mod limiter;
+#[cfg(feature = "std")]
+pub mod metrics;
+#[cfg(feature = "std")]
+pub mod operations;
+#[cfg(feature = "std")]
+pub mod pipeline;
+#[cfg(feature = "std")]
+pub mod plugin;
+#[cfg(feature = "std")]
+pub mod reload;
+#[cfg(feature = "std")]
+pub mod report;
+#[cfg(feature = "std")]
+pub mod shutdown;
+#[cfg(feature = "std")]
+pub mod snapshot;
+#[cfg(feature = "std")]
+pub mod state;
+
 pub use error::{Error, Result};
 
 /// Core functionality module
 pub mod core {
     use crate::Result;
 
-    /// Initialize the core system
+    #[cfg(feature = "std")]
+    static PLUGINS: std::sync::Mutex<Option<crate::plugin::PluginHost>> =
+        std::sync::Mutex::new(None);
+
+    /// Initialize the core system and every registered plugin
     pub fn init() -> Result<()> {
-        println!("Initializing core system");
+        #[cfg(feature = "std")]
+        {
+            use crate::plugin::{self, PluginHost};
+
+            eprintln!("Initializing core system");
+            let host = PluginHost::start(&plugin::registered())?;
+            let previous = PLUGINS
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .replace(host);
+            if let Some(mut previous) = previous {
+                previous.shutdown()?;
+            }
+        }
         Ok(())
     }
+
+    /// Health of the plugins started by [`init`]; degraded before initialization
+    #[cfg(feature = "std")]
+    pub fn plugin_health() -> crate::health::CheckResult {
+        PLUGINS
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .as_ref()
+            .map_or_else(
+                || crate::health::CheckResult::degraded("not initialized"),
+                |host| host.health(),
+            )
+    }
+
+    /// Shut down the plugins started by [`init`], in reverse order
+    #[cfg(feature = "std")]
+    pub fn shutdown() -> Result<()> {
+        let host = PLUGINS
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .take();
+        host.map_or(Ok(()), |mut host| host.shutdown())
+    }
 }
 
 #[cfg(test)]
@@ -31,4 +108,4 @@ mod tests {
     fn test_core_init() {
         assert!(core::init().is_ok());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,250 @@
+//! Concurrency limiting for operations
+//!
+//! [`ConcurrencyLimiter`] hands out RAII [`OperationPermit`]s up to a fixed
+//! capacity. Permits can be acquired by blocking the current thread or by
+//! awaiting a runtime-agnostic future, and release their slot when dropped.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+struct State {
+    active: usize,
+    capacity: usize,
+    wakers: Vec<Waker>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: Mutex<State>,
+    released: Condvar,
+}
+
+impl Inner {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Take a slot if one is free
+    fn try_take(self: &Arc<Self>, state: &mut State) -> Option<OperationPermit> {
+        (state.active < state.capacity).then(|| {
+            state.active += 1;
+            OperationPermit {
+                inner: Arc::clone(self),
+            }
+        })
+    }
+
+    /// Wake every waiter so they can re-check for a free slot
+    fn notify(&self, state: &mut State) {
+        self.released.notify_all();
+        for waker in state.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// Semaphore-style limiter capping the number of concurrent operations
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimiter {
+    inner: Arc<Inner>,
+}
+
+impl ConcurrencyLimiter {
+    /// Create a limiter allowing `capacity` concurrent permits
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                state: Mutex::new(State {
+                    active: 0,
+                    capacity,
+                    wakers: Vec::new(),
+                }),
+                released: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Number of permits currently held
+    pub fn active(&self) -> usize {
+        self.inner.lock().active
+    }
+
+    /// Maximum number of permits that can be held at once
+    pub fn capacity(&self) -> usize {
+        self.inner.lock().capacity
+    }
+
+    /// Change the capacity; held permits stay valid even when it shrinks
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut state = self.inner.lock();
+        state.capacity = capacity;
+        self.inner.notify(&mut state);
+    }
+
+    /// Take a permit if one is free right now
+    pub fn try_acquire(&self) -> Option<OperationPermit> {
+        self.inner.try_take(&mut self.inner.lock())
+    }
+
+    /// Block the current thread until a permit is free
+    pub fn acquire(&self) -> OperationPermit {
+        let mut state = self.inner.lock();
+        loop {
+            if let Some(permit) = self.inner.try_take(&mut state) {
+                return permit;
+            }
+            state = self
+                .inner
+                .released
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Block the current thread until a permit is free or `timeout` elapses
+    pub fn try_acquire_for(&self, timeout: Duration) -> Option<OperationPermit> {
+        // A timeout past the end of time never elapses
+        let Some(deadline) = Instant::now().checked_add(timeout) else {
+            return Some(self.acquire());
+        };
+        let mut state = self.inner.lock();
+        loop {
+            if let Some(permit) = self.inner.try_take(&mut state) {
+                return Some(permit);
+            }
+            let remaining = deadline.checked_duration_since(Instant::now())?;
+            state = self
+                .inner
+                .released
+                .wait_timeout(state, remaining)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+
+    /// Wait asynchronously until a permit is free
+    pub fn acquire_async(&self) -> Acquire {
+        Acquire {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+
+    /// Wait asynchronously until a permit is free or `timeout` elapses
+    #[cfg(feature = "async")]
+    pub async fn try_acquire_async_for(&self, timeout: Duration) -> Option<OperationPermit> {
+        tokio::time::timeout(timeout, self.acquire_async())
+            .await
+            .ok()
+    }
+}
+
+/// Future returned by [`ConcurrencyLimiter::acquire_async`]
+#[derive(Debug)]
+#[must_use = "futures do nothing unless awaited"]
+pub struct Acquire {
+    inner: Arc<Inner>,
+}
+
+impl Future for Acquire {
+    type Output = OperationPermit;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.inner.lock();
+        if let Some(permit) = self.inner.try_take(&mut state) {
+            return Poll::Ready(permit);
+        }
+        if !state.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            state.wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+/// Slot in a [`ConcurrencyLimiter`], released when dropped
+#[derive(Debug)]
+#[must_use = "the slot is released as soon as the permit is dropped"]
+pub struct OperationPermit {
+    inner: Arc<Inner>,
+}
+
+impl Drop for OperationPermit {
+    fn drop(&mut self) {
+        let mut state = self.inner.lock();
+        state.active -= 1;
+        self.inner.notify(&mut state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_permits_are_capped_and_released() {
+        let limiter = ConcurrencyLimiter::new(2);
+        let first = limiter.acquire();
+        let _second = limiter.acquire();
+        assert_eq!(limiter.active(), 2);
+        assert!(limiter.try_acquire().is_none());
+        assert!(limiter.try_acquire_for(Duration::from_millis(10)).is_none());
+
+        drop(first);
+        assert_eq!(limiter.active(), 1);
+        assert!(limiter.try_acquire().is_some());
+    }
+
+    #[test]
+    fn test_blocking_acquire_waits_for_release() {
+        let limiter = ConcurrencyLimiter::new(1);
+        let permit = limiter.acquire();
+        let waiter = {
+            let limiter = limiter.clone();
+            thread::spawn(move || {
+                let _permit = limiter.acquire();
+            })
+        };
+        thread::sleep(Duration::from_millis(20));
+        drop(permit);
+        waiter.join().unwrap();
+        assert_eq!(limiter.active(), 0);
+    }
+
+    #[test]
+    fn test_unbounded_timeout_waits_for_release() {
+        let limiter = ConcurrencyLimiter::new(1);
+        let permit = limiter.acquire();
+        let waiter = {
+            let limiter = limiter.clone();
+            thread::spawn(move || limiter.try_acquire_for(Duration::MAX).is_some())
+        };
+        thread::sleep(Duration::from_millis(20));
+        drop(permit);
+        assert!(waiter.join().unwrap());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_acquire() {
+        let limiter = ConcurrencyLimiter::new(1);
+        let permit = limiter.acquire_async().await;
+        assert!(
+            limiter
+                .try_acquire_async_for(Duration::from_millis(10))
+                .await
+                .is_none()
+        );
+
+        let waiter = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire_async().await }
+        });
+        drop(permit);
+        let _permit = waiter.await.unwrap();
+        assert_eq!(limiter.active(), 1);
+    }
+}
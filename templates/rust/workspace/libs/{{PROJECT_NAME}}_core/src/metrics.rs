@@ -0,0 +1,799 @@
+//! Counters, gauges and histograms with Prometheus text exposition
+//!
+//! Metrics are registered by name and label set on a [`MetricsRegistry`],
+//! which hands out cheap cloneable handles. [`AppState`](crate::types::AppState)
+//! owns a registry with built-in metrics for operations and errors, and
+//! persists its counters and histograms in state snapshots.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use serde::{Deserialize, Serialize};
+
+use crate::events::{Event, EventBus};
+use crate::{Error, Result};
+
+/// Number of operations currently running
+pub const OPERATIONS_ACTIVE: &str = "{{PROJECT_NAME}}_operations_active";
+/// Duration of finished operations, labelled by `operation`
+pub const OPERATION_DURATION: &str = "{{PROJECT_NAME}}_operation_duration_seconds";
+/// Errors by `code` and `variant` of [`Error`]
+pub const ERRORS_TOTAL: &str = "{{PROJECT_NAME}}_errors_total";
+
+/// Default histogram buckets in seconds, as used by Prometheus clients
+pub const DEFAULT_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Type of a metric family
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricKind {
+    /// Monotonically increasing count
+    Counter,
+    /// Value that can go up and down
+    Gauge,
+    /// Distribution of observed values in buckets
+    Histogram,
+}
+
+impl fmt::Display for MetricKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Counter => "counter",
+            Self::Gauge => "gauge",
+            Self::Histogram => "histogram",
+        })
+    }
+}
+
+/// Output format of a metrics dump
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MetricsFormat {
+    /// Prometheus text exposition format
+    #[default]
+    Prometheus,
+    /// JSON document of a [`MetricsSnapshot`]
+    Json,
+}
+
+impl FromStr for MetricsFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "prometheus" | "text" => Ok(Self::Prometheus),
+            "json" => Ok(Self::Json),
+            _ => Err(Error::Usage(format!(
+                "unknown metrics format {s:?} (expected prometheus or json)"
+            ))),
+        }
+    }
+}
+
+/// Handle to a counter
+#[derive(Debug, Clone)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    fn new() -> Self {
+        Self(Arc::default())
+    }
+
+    /// Add one
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    /// Add `n`
+    pub fn inc_by(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Current value
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Handle to a gauge
+#[derive(Debug, Clone)]
+pub struct Gauge(Arc<AtomicU64>);
+
+impl Gauge {
+    fn new() -> Self {
+        Self(Arc::new(AtomicU64::new(0f64.to_bits())))
+    }
+
+    /// Set the value
+    pub fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Add `delta`, which may be negative
+    pub fn add(&self, delta: f64) {
+        let _ = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + delta).to_bits())
+            });
+    }
+
+    /// Current value
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug)]
+struct HistogramData {
+    bounds: Vec<f64>,
+    /// Observations per bucket, with a final overflow bucket
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+/// Handle to a histogram
+#[derive(Debug, Clone)]
+pub struct Histogram(Arc<Mutex<HistogramData>>);
+
+impl Histogram {
+    fn new(buckets: &[f64]) -> Self {
+        Self(Arc::new(Mutex::new(HistogramData {
+            bounds: buckets.to_vec(),
+            counts: vec![0; buckets.len() + 1],
+            sum: 0.0,
+            count: 0,
+        })))
+    }
+
+    fn data(&self) -> MutexGuard<'_, HistogramData> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Record one observation
+    pub fn observe(&self, value: f64) {
+        let mut data = self.data();
+        let bucket = data.bounds.partition_point(|bound| *bound < value);
+        data.counts[bucket] += 1;
+        data.sum += value;
+        data.count += 1;
+    }
+
+    /// Number of observations
+    pub fn count(&self) -> u64 {
+        self.data().count
+    }
+
+    /// Sum of all observations
+    pub fn sum(&self) -> f64 {
+        self.data().sum
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        let data = self.data();
+        let mut cumulative = 0;
+        let buckets = data
+            .bounds
+            .iter()
+            .zip(&data.counts)
+            .map(|(le, count)| {
+                cumulative += count;
+                Bucket {
+                    le: *le,
+                    count: cumulative,
+                }
+            })
+            .collect();
+        HistogramSnapshot {
+            buckets,
+            sum: data.sum,
+            count: data.count,
+        }
+    }
+
+    fn merge(&self, snapshot: &HistogramSnapshot) {
+        let mut data = self.data();
+        let bounds: Vec<_> = snapshot.buckets.iter().map(|bucket| bucket.le).collect();
+        if bounds != data.bounds {
+            tracing::warn!("histogram buckets changed, previous observations dropped");
+            return;
+        }
+        let mut previous = 0;
+        for (index, bucket) in snapshot.buckets.iter().enumerate() {
+            data.counts[index] += bucket.count.saturating_sub(previous);
+            previous = bucket.count;
+        }
+        *data.counts.last_mut().expect("overflow bucket") +=
+            snapshot.count.saturating_sub(previous);
+        data.sum += snapshot.sum;
+        data.count += snapshot.count;
+    }
+}
+
+type Labels = BTreeMap<String, String>;
+
+#[derive(Debug)]
+enum Series {
+    Counter(Counter),
+    Gauge(Gauge),
+    Histogram(Histogram),
+}
+
+impl Series {
+    fn value(&self) -> SeriesValue {
+        match self {
+            Self::Counter(counter) => SeriesValue::Counter(counter.get()),
+            Self::Gauge(gauge) => SeriesValue::Gauge(gauge.get()),
+            Self::Histogram(histogram) => SeriesValue::Histogram(histogram.snapshot()),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Family {
+    help: String,
+    kind: MetricKind,
+    series: BTreeMap<Labels, Series>,
+}
+
+/// Cloneable registry of metric families
+#[derive(Debug, Clone, Default)]
+pub struct MetricsRegistry {
+    families: Arc<Mutex<BTreeMap<String, Family>>>,
+}
+
+impl MetricsRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get or create the series `labels` of family `name`
+    ///
+    /// Fails if `name` is already registered with a different kind.
+    fn series<T>(
+        &self,
+        name: &str,
+        help: &str,
+        kind: MetricKind,
+        labels: &[(&str, &str)],
+        create: impl FnOnce() -> Series,
+        extract: impl FnOnce(&Series) -> Option<T>,
+    ) -> Result<T> {
+        let mut families = self.families.lock().unwrap_or_else(PoisonError::into_inner);
+        let family = families.entry(name.to_string()).or_insert_with(|| Family {
+            help: help.to_string(),
+            kind,
+            series: BTreeMap::new(),
+        });
+        if family.kind != kind {
+            return Err(Error::Other(format!(
+                "metric {name} is already registered as a {}",
+                family.kind
+            )));
+        }
+        let labels = labels
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        extract(family.series.entry(labels).or_insert_with(create))
+            .ok_or_else(|| Error::Other(format!("metric {name} holds a series of another kind")))
+    }
+
+    /// Get or create a counter
+    ///
+    /// If `name` is registered as another kind, a warning is logged and the
+    /// counter returned is not part of the registry.
+    pub fn counter(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Counter {
+        self.try_counter(name, help, labels)
+            .unwrap_or_else(|e| unregistered(&e, Counter::new()))
+    }
+
+    fn try_counter(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Result<Counter> {
+        self.series(
+            name,
+            help,
+            MetricKind::Counter,
+            labels,
+            || Series::Counter(Counter::new()),
+            |series| match series {
+                Series::Counter(counter) => Some(counter.clone()),
+                _ => None,
+            },
+        )
+    }
+
+    /// Get or create a gauge
+    ///
+    /// If `name` is registered as another kind, a warning is logged and the
+    /// gauge returned is not part of the registry.
+    pub fn gauge(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Gauge {
+        self.series(
+            name,
+            help,
+            MetricKind::Gauge,
+            labels,
+            || Series::Gauge(Gauge::new()),
+            |series| match series {
+                Series::Gauge(gauge) => Some(gauge.clone()),
+                _ => None,
+            },
+        )
+        .unwrap_or_else(|e| unregistered(&e, Gauge::new()))
+    }
+
+    /// Get or create a histogram with ascending bucket upper bounds
+    ///
+    /// If `name` is registered as another kind, a warning is logged and the
+    /// histogram returned is not part of the registry.
+    pub fn histogram(
+        &self,
+        name: &str,
+        help: &str,
+        labels: &[(&str, &str)],
+        buckets: &[f64],
+    ) -> Histogram {
+        self.try_histogram(name, help, labels, buckets)
+            .unwrap_or_else(|e| unregistered(&e, Histogram::new(buckets)))
+    }
+
+    fn try_histogram(
+        &self,
+        name: &str,
+        help: &str,
+        labels: &[(&str, &str)],
+        buckets: &[f64],
+    ) -> Result<Histogram> {
+        self.series(
+            name,
+            help,
+            MetricKind::Histogram,
+            labels,
+            || Series::Histogram(Histogram::new(buckets)),
+            |series| match series {
+                Series::Histogram(histogram) => Some(histogram.clone()),
+                _ => None,
+            },
+        )
+    }
+
+    /// Count `error` in [`ERRORS_TOTAL`] by the first [`Error`] in its source chain
+    pub fn record_error(&self, error: &(dyn std::error::Error + 'static)) {
+        let core = std::iter::successors(Some(error), |e| e.source())
+            .find_map(|e| e.downcast_ref::<Error>());
+        let (code, variant) = core.map_or(("none", "Unknown"), |e| (e.code(), e.name()));
+        self.counter(
+            ERRORS_TOTAL,
+            "Errors by code and variant",
+            &[("code", code), ("variant", variant)],
+        )
+        .inc();
+    }
+
+    /// Maintain the built-in operation metrics from events on `events`
+    pub(crate) fn instrument(&self, events: &EventBus) {
+        let active = self.gauge(OPERATIONS_ACTIVE, "Operations currently running", &[]);
+        let metrics = self.clone();
+        events.on_event(move |event| match event {
+            Event::OperationStarted { .. } => active.add(1.0),
+            Event::OperationFinished { name, duration, .. } => {
+                active.add(-1.0);
+                metrics
+                    .histogram(
+                        OPERATION_DURATION,
+                        "Duration of finished operations in seconds",
+                        &[("operation", name)],
+                        DEFAULT_BUCKETS,
+                    )
+                    .observe(duration.as_secs_f64());
+            }
+            _ => {}
+        });
+    }
+
+    /// Point-in-time copy of every metric
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let families = self.families.lock().unwrap_or_else(PoisonError::into_inner);
+        MetricsSnapshot {
+            families: families
+                .iter()
+                .map(|(name, family)| FamilySnapshot {
+                    name: name.clone(),
+                    help: family.help.clone(),
+                    kind: family.kind,
+                    series: family
+                        .series
+                        .iter()
+                        .map(|(labels, series)| SeriesSnapshot {
+                            labels: labels.clone(),
+                            value: series.value(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Add the counters and histograms of `snapshot`, e.g. after a restart
+    ///
+    /// Gauges describe the running process and are skipped, as are families
+    /// whose series do not match their kind or a metric of another kind.
+    pub fn restore(&self, snapshot: &MetricsSnapshot) {
+        for family in &snapshot.families {
+            if let Err(e) = family.validate().and_then(|()| self.restore_family(family)) {
+                tracing::warn!(error = %e, "metric family not restored");
+            }
+        }
+    }
+
+    fn restore_family(&self, family: &FamilySnapshot) -> Result<()> {
+        for series in &family.series {
+            let labels: Vec<_> = series
+                .labels
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str()))
+                .collect();
+            match &series.value {
+                SeriesValue::Counter(value) => self
+                    .try_counter(&family.name, &family.help, &labels)?
+                    .inc_by(*value),
+                SeriesValue::Histogram(histogram) => {
+                    let bounds: Vec<_> = histogram.buckets.iter().map(|bucket| bucket.le).collect();
+                    self.try_histogram(&family.name, &family.help, &labels, &bounds)?
+                        .merge(histogram);
+                }
+                SeriesValue::Gauge(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Log that a metric could not be registered and hand out `detached` instead
+fn unregistered<T>(error: &Error, detached: T) -> T {
+    tracing::warn!(error = %error, "metric not registered");
+    detached
+}
+
+/// Serializable copy of a [`MetricsRegistry`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MetricsSnapshot {
+    /// Metric families, sorted by name
+    pub families: Vec<FamilySnapshot>,
+}
+
+/// All series of one metric name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FamilySnapshot {
+    /// Metric name
+    pub name: String,
+    /// Description shown as `# HELP`
+    pub help: String,
+    /// Metric type
+    pub kind: MetricKind,
+    /// One entry per label set
+    pub series: Vec<SeriesSnapshot>,
+}
+
+/// Value of one label set
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SeriesSnapshot {
+    /// Label names and values
+    pub labels: BTreeMap<String, String>,
+    /// Current value
+    pub value: SeriesValue,
+}
+
+/// Value of a series
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SeriesValue {
+    /// Counter value
+    Counter(u64),
+    /// Gauge value
+    Gauge(f64),
+    /// Histogram buckets, sum and count
+    Histogram(HistogramSnapshot),
+}
+
+/// Cumulative histogram buckets
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistogramSnapshot {
+    /// Observations less than or equal to each upper bound, excluding `+Inf`
+    pub buckets: Vec<Bucket>,
+    /// Sum of all observations
+    pub sum: f64,
+    /// Number of observations, i.e. the `+Inf` bucket
+    pub count: u64,
+}
+
+/// Histogram bucket
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bucket {
+    /// Upper bound
+    pub le: f64,
+    /// Cumulative count
+    pub count: u64,
+}
+
+impl FamilySnapshot {
+    /// Check that every series holds a value of the family's kind
+    pub fn validate(&self) -> Result<()> {
+        match self
+            .series
+            .iter()
+            .find(|series| series.value.kind() != self.kind)
+        {
+            Some(series) => Err(Error::Other(format!(
+                "metric {} is a {} but holds a {} series",
+                self.name,
+                self.kind,
+                series.value.kind()
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl SeriesValue {
+    /// Kind of metric this value belongs to
+    pub fn kind(&self) -> MetricKind {
+        match self {
+            Self::Counter(_) => MetricKind::Counter,
+            Self::Gauge(_) => MetricKind::Gauge,
+            Self::Histogram(_) => MetricKind::Histogram,
+        }
+    }
+}
+
+impl MetricsSnapshot {
+    /// Check that every family holds series of its kind
+    pub fn validate(&self) -> Result<()> {
+        self.families.iter().try_for_each(FamilySnapshot::validate)
+    }
+
+    /// Render in `format`
+    pub fn render(&self, format: MetricsFormat) -> Result<String> {
+        match format {
+            MetricsFormat::Prometheus => Ok(self.to_prometheus()),
+            MetricsFormat::Json => serde_json::to_string_pretty(self)
+                .map(|json| json + "\n")
+                .map_err(|e| Error::Other(format!("cannot serialize metrics: {e}"))),
+        }
+    }
+
+    /// Render in the Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        for family in &self.families {
+            let name = &family.name;
+            let _ = writeln!(out, "# HELP {name} {}", escape(&family.help, false));
+            let _ = writeln!(out, "# TYPE {name} {}", family.kind);
+            for series in &family.series {
+                match &series.value {
+                    SeriesValue::Counter(value) => {
+                        let _ = writeln!(out, "{name}{} {value}", labels(&series.labels, None));
+                    }
+                    SeriesValue::Gauge(value) => {
+                        let _ = writeln!(
+                            out,
+                            "{name}{} {}",
+                            labels(&series.labels, None),
+                            number(*value)
+                        );
+                    }
+                    SeriesValue::Histogram(histogram) => {
+                        for bucket in &histogram.buckets {
+                            let le = number(bucket.le);
+                            let _ = writeln!(
+                                out,
+                                "{name}_bucket{} {}",
+                                labels(&series.labels, Some(&le)),
+                                bucket.count
+                            );
+                        }
+                        let labelled = labels(&series.labels, None);
+                        let _ = writeln!(
+                            out,
+                            "{name}_bucket{} {}",
+                            labels(&series.labels, Some("+Inf")),
+                            histogram.count
+                        );
+                        let _ = writeln!(out, "{name}_sum{labelled} {}", number(histogram.sum));
+                        let _ = writeln!(out, "{name}_count{labelled} {}", histogram.count);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Format `{key="value",...}`, with an optional trailing `le` label
+fn labels(labels: &Labels, le: Option<&str>) -> String {
+    let pairs: Vec<_> = labels
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .chain(le.map(|le| ("le", le)))
+        .map(|(key, value)| format!("{key}=\"{}\"", escape(value, true)))
+        .collect();
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+/// Escape backslashes and newlines, and double quotes in label values
+fn escape(s: &str, quotes: bool) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '"' if quotes => escaped.push_str("\\\""),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Format a sample value the way Prometheus parses it
+fn number(value: f64) -> String {
+    if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else if value.is_nan() {
+        "NaN".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_counters_gauges_and_histograms() {
+        let metrics = MetricsRegistry::new();
+        let requests = metrics.counter("requests_total", "Requests", &[("path", "/")]);
+        requests.inc();
+        metrics
+            .counter("requests_total", "Requests", &[("path", "/")])
+            .inc_by(2);
+        assert_eq!(requests.get(), 3);
+
+        let queue = metrics.gauge("queue_depth", "Queued jobs", &[]);
+        queue.add(5.0);
+        queue.add(-2.0);
+        assert_eq!(queue.get(), 3.0);
+
+        let latency = metrics.histogram("latency_seconds", "Latency", &[], &[0.1, 1.0]);
+        for value in [0.05, 0.1, 0.5, 3.0] {
+            latency.observe(value);
+        }
+        let histogram = latency.snapshot();
+        let counts: Vec<_> = histogram.buckets.iter().map(|b| b.count).collect();
+        assert_eq!(counts, [2, 3]);
+        assert_eq!(histogram.count, 4);
+    }
+
+    #[test]
+    fn test_kind_mismatch_is_not_registered() {
+        let metrics = MetricsRegistry::new();
+        metrics.counter("value", "Value", &[]).inc();
+        metrics.gauge("value", "Value", &[]).set(5.0);
+        assert_eq!(metrics.counter("value", "Value", &[]).get(), 1);
+        assert_eq!(metrics.snapshot().families[0].kind, MetricKind::Counter);
+    }
+
+    #[test]
+    fn test_prometheus_text() {
+        let metrics = MetricsRegistry::new();
+        metrics
+            .counter(
+                "errors_total",
+                "Errors",
+                &[("code", "E0002"), ("msg", "a \"b\"")],
+            )
+            .inc();
+        metrics
+            .histogram("duration_seconds", "Duration", &[], &[0.5])
+            .observe(0.25);
+
+        assert_eq!(
+            metrics.snapshot().to_prometheus(),
+            "# HELP duration_seconds Duration\n\
+             # TYPE duration_seconds histogram\n\
+             duration_seconds_bucket{le=\"0.5\"} 1\n\
+             duration_seconds_bucket{le=\"+Inf\"} 1\n\
+             duration_seconds_sum 0.25\n\
+             duration_seconds_count 1\n\
+             # HELP errors_total Errors\n\
+             # TYPE errors_total counter\n\
+             errors_total{code=\"E0002\",msg=\"a \\\"b\\\"\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn test_restore_adds_counters_and_histograms() {
+        let before = MetricsRegistry::new();
+        before.counter("runs_total", "Runs", &[]).inc_by(2);
+        before.gauge("active", "Active", &[]).set(7.0);
+        before
+            .histogram("seconds", "Seconds", &[], &[1.0])
+            .observe(2.0);
+        let json = before.snapshot().render(MetricsFormat::Json).unwrap();
+        let snapshot: MetricsSnapshot = serde_json::from_str(&json).unwrap();
+
+        let after = MetricsRegistry::new();
+        after.counter("runs_total", "Runs", &[]).inc();
+        after.restore(&snapshot);
+        assert_eq!(after.counter("runs_total", "Runs", &[]).get(), 3);
+        assert_eq!(after.gauge("active", "Active", &[]).get(), 0.0);
+        let seconds = after.histogram("seconds", "Seconds", &[], &[1.0]);
+        seconds.observe(0.5);
+        assert_eq!(seconds.count(), 2);
+        assert_eq!(seconds.snapshot().buckets[0].count, 1);
+    }
+
+    #[test]
+    fn test_restore_skips_mismatched_families() {
+        let before = MetricsRegistry::new();
+        before.counter("runs_total", "Runs", &[]).inc_by(2);
+        before.counter("seconds", "Seconds", &[]).inc();
+        let mut snapshot = before.snapshot();
+        // A family claiming another kind than its series
+        snapshot.families[0].kind = MetricKind::Histogram;
+        assert!(snapshot.validate().is_err());
+
+        let after = MetricsRegistry::new();
+        // Registered as another kind than in the snapshot
+        after
+            .histogram("seconds", "Seconds", &[], &[1.0])
+            .observe(0.5);
+        after.restore(&snapshot);
+        assert_eq!(after.snapshot().families.len(), 1);
+        assert_eq!(
+            after.histogram("seconds", "Seconds", &[], &[1.0]).count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_builtin_metrics() {
+        let metrics = MetricsRegistry::new();
+        let events = EventBus::new();
+        metrics.instrument(&events);
+        let id = "op-1".parse().unwrap();
+        events.publish(Event::OperationStarted {
+            id,
+            name: "run".to_string(),
+        });
+        assert_eq!(metrics.gauge(OPERATIONS_ACTIVE, "", &[]).get(), 1.0);
+        events.publish(Event::OperationFinished {
+            id,
+            name: "run".to_string(),
+            duration: Duration::from_millis(20),
+            cancelled: false,
+        });
+        assert_eq!(metrics.gauge(OPERATIONS_ACTIVE, "", &[]).get(), 0.0);
+        let duration = metrics.histogram(OPERATION_DURATION, "", &[("operation", "run")], &[]);
+        assert_eq!(duration.count(), 1);
+
+        let error = anyhow::Error::from(Error::Config("missing".to_string())).context("loading");
+        metrics.record_error(error.as_ref());
+        let errors = metrics.counter(
+            ERRORS_TOTAL,
+            "",
+            &[("code", "E0002"), ("variant", "Config")],
+        );
+        assert_eq!(errors.get(), 1);
+    }
+}
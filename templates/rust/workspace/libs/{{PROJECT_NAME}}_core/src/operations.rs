@@ -0,0 +1,407 @@
+//! Registry of running operations
+//!
+//! Every operation registered with an [`OperationRegistry`] gets a unique
+//! [`OperationId`], a start time, a progress value and a
+//! [`CancellationToken`]. Operations deregister themselves when their
+//! [`Operation`] handle is dropped, and are then recorded in the registry's
+//! [`History`].
+//!
+//! The registry is in-process: it lets a long-running application inspect and
+//! cancel its own work. Only the history is persisted (see
+//! [`snapshot`](crate::snapshot)), so other processes never see running operations.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::events::{Event, EventBus};
+use crate::limiter::OperationPermit;
+use crate::{Error, Result};
+
+/// Unique identifier of a registered operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OperationId(u64);
+
+impl fmt::Display for OperationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "op-{}", self.0)
+    }
+}
+
+impl FromStr for OperationId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.strip_prefix("op-")
+            .unwrap_or(s)
+            .parse()
+            .map(Self)
+            .map_err(|_| Error::Other(format!("invalid operation id: {s}")))
+    }
+}
+
+/// Cooperative cancellation flag shared between an operation and its controllers
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Create a token that is not cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether cancellation has been requested
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Point-in-time view of a registered operation
+#[derive(Debug, Clone, PartialEq)]
+pub struct OperationInfo {
+    /// Unique identifier
+    pub id: OperationId,
+    /// Human-readable name
+    pub name: String,
+    /// When the operation was registered
+    pub started_at: SystemTime,
+    /// Progress between 0.0 and 1.0
+    pub progress: f32,
+    /// Whether cancellation has been requested
+    pub cancelled: bool,
+}
+
+impl OperationInfo {
+    /// Time since the operation started
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.elapsed().unwrap_or_default()
+    }
+}
+
+impl fmt::Display for OperationInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {:.0}% ({:.1}s)",
+            self.id,
+            self.name,
+            self.progress * 100.0,
+            self.elapsed().as_secs_f32()
+        )?;
+        if self.cancelled {
+            write!(f, " [cancelling]")?;
+        }
+        Ok(())
+    }
+}
+
+/// Record of a finished operation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Human-readable name
+    pub name: String,
+    /// When the operation was registered
+    pub started_at: SystemTime,
+    /// How long the operation ran
+    pub duration: Duration,
+    /// Whether cancellation had been requested when it finished
+    pub cancelled: bool,
+}
+
+/// Counters and recent history of finished operations
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct History {
+    /// Number of finished operations by name
+    pub counters: BTreeMap<String, u64>,
+    /// Most recently finished operations, oldest first
+    pub recent: VecDeque<HistoryEntry>,
+}
+
+impl History {
+    /// Number of finished operations kept in `recent`
+    pub const RECENT_LIMIT: usize = 100;
+
+    /// Count `entry` and append it to the recent history
+    pub fn record(&mut self, entry: HistoryEntry) {
+        *self.counters.entry(entry.name.clone()).or_default() += 1;
+        self.recent.push_back(entry);
+        while self.recent.len() > Self::RECENT_LIMIT {
+            self.recent.pop_front();
+        }
+    }
+
+    /// Total number of finished operations
+    pub fn total(&self) -> u64 {
+        self.counters.values().sum()
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    name: String,
+    started_at: SystemTime,
+    progress: f32,
+    token: CancellationToken,
+}
+
+impl Entry {
+    fn info(&self, id: OperationId) -> OperationInfo {
+        OperationInfo {
+            id,
+            name: self.name.clone(),
+            started_at: self.started_at,
+            progress: self.progress,
+            cancelled: self.token.is_cancelled(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    next_id: AtomicU64,
+    entries: Mutex<BTreeMap<OperationId, Entry>>,
+    history: Mutex<History>,
+    events: EventBus,
+}
+
+impl Inner {
+    fn entries(&self) -> MutexGuard<'_, BTreeMap<OperationId, Entry>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn history(&self) -> MutexGuard<'_, History> {
+        self.history.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Registry of running operations that can be listed, inspected and cancelled
+#[derive(Debug, Clone, Default)]
+pub struct OperationRegistry {
+    inner: Arc<Inner>,
+}
+
+impl OperationRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty registry that publishes operation events on `events`
+    pub fn with_events(events: EventBus) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                events,
+                ..Inner::default()
+            }),
+        }
+    }
+
+    /// Bus on which operation events are published
+    pub fn events(&self) -> &EventBus {
+        &self.inner.events
+    }
+
+    /// Register a new operation; it is removed when the returned handle is dropped
+    pub fn register(&self, name: impl Into<String>) -> Operation {
+        let id = OperationId(self.inner.next_id.fetch_add(1, Ordering::Relaxed) + 1);
+        let token = CancellationToken::new();
+        let name = name.into();
+        self.inner.entries().insert(
+            id,
+            Entry {
+                name: name.clone(),
+                started_at: SystemTime::now(),
+                progress: 0.0,
+                token: token.clone(),
+            },
+        );
+        self.inner
+            .events
+            .publish(Event::OperationStarted { id, name });
+        Operation {
+            id,
+            token,
+            registry: Arc::clone(&self.inner),
+            _permit: None,
+        }
+    }
+
+    /// All running operations, oldest first
+    pub fn list(&self) -> Vec<OperationInfo> {
+        self.inner
+            .entries()
+            .iter()
+            .map(|(id, entry)| entry.info(*id))
+            .collect()
+    }
+
+    /// Inspect a single operation
+    pub fn get(&self, id: OperationId) -> Option<OperationInfo> {
+        self.inner.entries().get(&id).map(|entry| entry.info(id))
+    }
+
+    /// Request cancellation of an operation
+    pub fn cancel(&self, id: OperationId) -> Result<()> {
+        self.inner
+            .entries()
+            .get(&id)
+            .map(|entry| entry.token.cancel())
+            .ok_or_else(|| Error::Other(format!("no running operation with id {id}")))
+    }
+
+    /// Request cancellation of every running operation
+    pub fn cancel_all(&self) {
+        for entry in self.inner.entries().values() {
+            entry.token.cancel();
+        }
+    }
+
+    /// Number of running operations
+    pub fn len(&self) -> usize {
+        self.inner.entries().len()
+    }
+
+    /// Whether no operation is running
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Counters and recent history of finished operations
+    pub fn history(&self) -> History {
+        self.inner.history().clone()
+    }
+
+    /// Replace the history, e.g. with one restored from a snapshot
+    pub fn restore_history(&self, history: History) {
+        *self.inner.history() = history;
+    }
+}
+
+/// Handle to a registered operation, deregistered when dropped
+#[derive(Debug)]
+#[must_use = "the operation is deregistered as soon as the handle is dropped"]
+pub struct Operation {
+    id: OperationId,
+    token: CancellationToken,
+    registry: Arc<Inner>,
+    _permit: Option<OperationPermit>,
+}
+
+impl Operation {
+    /// Unique identifier of this operation
+    pub fn id(&self) -> OperationId {
+        self.id
+    }
+
+    /// Cancellation token to poll or hand to sub-tasks
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    /// Whether cancellation has been requested
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Report progress between 0.0 and 1.0
+    pub fn set_progress(&self, progress: f32) {
+        if let Some(entry) = self.registry.entries().get_mut(&self.id) {
+            entry.progress = progress.clamp(0.0, 1.0);
+        }
+    }
+
+    /// Hold a concurrency permit for as long as this operation runs
+    pub(crate) fn with_permit(mut self, permit: OperationPermit) -> Self {
+        self._permit = Some(permit);
+        self
+    }
+}
+
+impl Drop for Operation {
+    fn drop(&mut self) {
+        let Some(entry) = self.registry.entries().remove(&self.id) else {
+            return;
+        };
+        let finished = HistoryEntry {
+            duration: entry.started_at.elapsed().unwrap_or_default(),
+            name: entry.name,
+            started_at: entry.started_at,
+            cancelled: entry.token.is_cancelled(),
+        };
+        self.registry.history().record(finished.clone());
+        self.registry.events.publish(Event::OperationFinished {
+            id: self.id,
+            name: finished.name,
+            duration: finished.duration,
+            cancelled: finished.cancelled,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_list_and_drop() {
+        let registry = OperationRegistry::new();
+        let first = registry.register("import");
+        let second = registry.register("export");
+        assert_ne!(first.id(), second.id());
+
+        second.set_progress(0.5);
+        let listed = registry.list();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].name, "import");
+        assert_eq!(registry.get(second.id()).unwrap().progress, 0.5);
+
+        drop(first);
+        assert_eq!(registry.len(), 1);
+    }
+
+    #[test]
+    fn test_cancel_by_id() {
+        let registry = OperationRegistry::new();
+        let operation = registry.register("sync");
+        let id: OperationId = operation.id().to_string().parse().unwrap();
+
+        registry.cancel(id).unwrap();
+        assert!(operation.is_cancelled());
+        assert!(registry.get(id).unwrap().cancelled);
+
+        drop(operation);
+        assert!(registry.cancel(id).is_err());
+    }
+
+    #[test]
+    fn test_finished_operations_are_recorded() {
+        let registry = OperationRegistry::new();
+        drop(registry.register("import"));
+        let cancelled = registry.register("import");
+        cancelled.token().cancel();
+        drop(cancelled);
+        drop(registry.register("export"));
+
+        let history = registry.history();
+        assert_eq!(history.counters["import"], 2);
+        assert_eq!(history.total(), 3);
+        assert!(history.recent[1].cancelled);
+        assert_eq!(history.recent[2].name, "export");
+
+        registry.restore_history(History::default());
+        assert_eq!(registry.history().total(), 0);
+    }
+}
@@ -0,0 +1,320 @@
+//! Record processing
+//!
+//! A [`Pipeline`] takes records parsed by an input format (see
+//! [`formats`](crate::formats)), hands each to a [`Processor`] and writes
+//! whatever it returns to a writer, one record per line: as a JSON object, or
+//! for plain text input as the line itself. A record that
+//! is malformed or that the processor rejects is counted and reported to the
+//! failure hooks, and the run carries on with the next one; only reading or
+//! writing failures abort it.
+
+use std::fmt;
+use std::io::Write;
+
+use crate::formats::LINE_FIELD;
+use crate::operations::CancellationToken;
+use crate::types::{Record, Value};
+use crate::{Error, Result};
+
+/// Transforms input records into output records
+pub trait Processor: Send {
+    /// Process one record; `Ok(None)` leaves it out of the output
+    fn process(&mut self, record: Record) -> Result<Option<Record>>;
+}
+
+impl<F> Processor for F
+where
+    F: FnMut(Record) -> Result<Option<Record>> + Send,
+{
+    fn process(&mut self, record: Record) -> Result<Option<Record>> {
+        self(record)
+    }
+}
+
+/// Processor writing every record unchanged
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Passthrough;
+
+impl Processor for Passthrough {
+    fn process(&mut self, record: Record) -> Result<Option<Record>> {
+        Ok(Some(record))
+    }
+}
+
+/// Record that was malformed or rejected by the processor
+#[derive(Debug)]
+pub struct RecordFailure {
+    /// Line of the record in the input, starting at 1
+    pub line: u64,
+    /// Why the record failed
+    pub error: Error,
+}
+
+impl fmt::Display for RecordFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.error {
+            // Already names the line and column
+            Error::Parse { .. } => write!(f, "{}", self.error),
+            _ => write!(f, "line {}: {}", self.line, self.error),
+        }
+    }
+}
+
+/// Record counts of a pipeline run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineStats {
+    /// Records processed successfully, including those left out of the output
+    pub processed: u64,
+    /// Records that were malformed or rejected
+    pub failed: u64,
+}
+
+impl fmt::Display for PipelineStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} processed, {} failed", self.processed, self.failed)
+    }
+}
+
+/// How a [`Pipeline`] writes output records
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// One JSON object of the record's fields per line
+    #[default]
+    JsonLines,
+    /// The [`LINE_FIELD`] of each record as-is; records without one as JSON
+    Text,
+}
+
+impl OutputFormat {
+    fn encode(self, record: &Record) -> Result<Vec<u8>> {
+        if let (Self::Text, Some(Value::String(line))) = (self, record.get(LINE_FIELD)) {
+            return Ok(line.clone().into_bytes());
+        }
+        serde_json::to_vec(&record.fields)
+            .map_err(|e| Error::Other(format!("cannot serialize record: {e}")))
+    }
+}
+
+type FailureHook = Box<dyn FnMut(&RecordFailure) + Send>;
+
+/// Streams records through a [`Processor`]
+pub struct Pipeline<P> {
+    processor: P,
+    output: OutputFormat,
+    token: Option<CancellationToken>,
+    hooks: Vec<FailureHook>,
+}
+
+impl<P> fmt::Debug for Pipeline<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("output", &self.output)
+            .field("token", &self.token)
+            .field("hooks", &self.hooks.len())
+            .finish()
+    }
+}
+
+impl<P: Processor> Pipeline<P> {
+    /// Process records with `processor`
+    pub fn new(processor: P) -> Self {
+        Self {
+            processor,
+            output: OutputFormat::default(),
+            token: None,
+            hooks: Vec::new(),
+        }
+    }
+
+    /// Write output records as `output` instead of JSON lines
+    pub fn with_output(mut self, output: OutputFormat) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Stop before the next record once `token` is cancelled
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Call `hook` for every malformed or rejected record
+    pub fn on_failure(mut self, hook: impl FnMut(&RecordFailure) + Send + 'static) -> Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// The processor, e.g. to read what it accumulated
+    pub fn processor(&self) -> &P {
+        &self.processor
+    }
+
+    /// Process `records`, writing the results to `writer`
+    ///
+    /// An [`Error::Io`] item aborts the run; other errors count as failed records.
+    pub fn run(
+        &mut self,
+        records: impl IntoIterator<Item = Result<Record>>,
+        mut writer: impl Write,
+    ) -> Result<PipelineStats> {
+        let mut stats = PipelineStats::default();
+        let cancelled = |stats| Error::Other(format!("cancelled ({stats})"));
+        for record in records {
+            if self.is_cancelled() {
+                return Err(cancelled(stats));
+            }
+
+            let (line, result) = match record {
+                Ok(record) => (record.line, self.processor.process(record)),
+                // Reading may have been cut short by the cancellation
+                Err(Error::Io(_)) if self.is_cancelled() => return Err(cancelled(stats)),
+                Err(Error::Io(e)) => return Err(Error::Io(e)),
+                Err(error) => {
+                    let line = match error {
+                        Error::Parse { line, .. } => line,
+                        _ => 0,
+                    };
+                    (line, Err(error))
+                }
+            };
+            let output = self.output;
+            let result =
+                result.and_then(|record| record.map(|record| output.encode(&record)).transpose());
+            match result {
+                Ok(output) => {
+                    stats.processed += 1;
+                    if let Some(mut output) = output {
+                        output.push(b'\n');
+                        writer.write_all(&output)?;
+                    }
+                }
+                Err(error) => {
+                    stats.failed += 1;
+                    let failure = RecordFailure { line, error };
+                    for hook in &mut self.hooks {
+                        hook(&failure);
+                    }
+                }
+            }
+        }
+        writer.flush()?;
+        Ok(stats)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.token
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Value;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    fn record(line: u64, name: &str) -> Result<Record> {
+        let mut record = Record::new(line);
+        record
+            .fields
+            .insert("name".to_string(), Value::String(name.to_string()));
+        Ok(record)
+    }
+
+    fn upper(mut record: Record) -> Result<Option<Record>> {
+        let Some(Value::String(name)) = record.fields.get_mut("name") else {
+            return Err(Error::Other("missing name".to_string()));
+        };
+        match name.as_str() {
+            "skip" => Ok(None),
+            "bad" => Err(Error::Other("rejected".to_string())),
+            _ => {
+                *name = name.to_uppercase();
+                Ok(Some(record))
+            }
+        }
+    }
+
+    #[test]
+    fn test_processes_records_and_counts_failures() {
+        let failures = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&failures);
+        let mut pipeline = Pipeline::new(upper)
+            .on_failure(move |failure| recorded.lock().unwrap().push(failure.to_string()));
+
+        let records = [
+            record(1, "alpha"),
+            record(2, "bad"),
+            Err(Error::Parse {
+                line: 3,
+                column: 4,
+                message: "expected value".to_string(),
+            }),
+            record(4, "skip"),
+            record(5, "beta"),
+        ];
+        let mut output = Vec::new();
+        let stats = pipeline.run(records, &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"name\":\"ALPHA\"}\n{\"name\":\"BETA\"}\n"
+        );
+        assert_eq!(
+            stats,
+            PipelineStats {
+                processed: 3,
+                failed: 2
+            }
+        );
+        assert_eq!(
+            *failures.lock().unwrap(),
+            [
+                "line 2: rejected",
+                "Parse error at line 3, column 4: expected value"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_text_output_writes_lines() {
+        let mut line = Record::new(1);
+        line.fields.insert(
+            LINE_FIELD.to_string(),
+            Value::String("plain {text}".to_string()),
+        );
+        let records = [Ok(line), record(2, "other")];
+        let mut output = Vec::new();
+        Pipeline::new(Passthrough)
+            .with_output(OutputFormat::Text)
+            .run(records, &mut output)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "plain {text}\n{\"name\":\"other\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_read_errors_abort() {
+        let records = [
+            record(1, "a"),
+            Err(Error::Io(io::Error::other("disk"))),
+            record(2, "b"),
+        ];
+        let result = Pipeline::new(Passthrough).run(records, io::sink());
+        assert!(matches!(result, Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn test_stops_when_cancelled() {
+        let token = CancellationToken::new();
+        token.cancel();
+        let result = Pipeline::new(Passthrough)
+            .with_cancellation(token)
+            .run([record(1, "a")], io::sink());
+        assert!(matches!(result, Err(Error::Other(_))));
+    }
+}
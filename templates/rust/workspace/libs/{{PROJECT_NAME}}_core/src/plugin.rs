@@ -0,0 +1,370 @@
+//! Compile-time plugin registry
+//!
+//! Any crate linked into the final binary can contribute a [`Plugin`] with
+//! [`register_plugin!`](crate::register_plugin). [`core::init`](crate::core::init)
+//! discovers every registered plugin, orders them by their declared
+//! dependencies and initializes them; they are shut down in reverse order.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::health::{CheckResult, HealthStatus};
+use crate::{Error, Result};
+
+#[doc(hidden)]
+pub use inventory;
+
+/// Extension that is initialized and shut down together with the core system
+pub trait Plugin: Send + Sync {
+    /// Unique plugin name
+    fn name(&self) -> &'static str;
+
+    /// Plugin version
+    fn version(&self) -> &'static str;
+
+    /// Names of plugins that must be initialized before this one
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Called once at startup, after all dependencies are initialized
+    fn init(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once at shutdown, before any dependency is shut down
+    fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Report the health of the plugin while it is running
+    fn health(&self) -> CheckResult {
+        CheckResult::healthy("running")
+    }
+}
+
+/// Entry in the compile-time plugin registry, created by [`register_plugin!`](crate::register_plugin)
+pub struct PluginRegistration {
+    plugin: &'static dyn Plugin,
+}
+
+impl PluginRegistration {
+    /// Wrap a plugin for submission to the registry
+    pub const fn new(plugin: &'static dyn Plugin) -> Self {
+        Self { plugin }
+    }
+}
+
+inventory::collect!(PluginRegistration);
+
+/// Add a plugin to the compile-time registry
+///
+/// ```ignore
+/// struct Metrics;
+///
+/// impl Plugin for Metrics {
+///     fn name(&self) -> &'static str { "metrics" }
+///     fn version(&self) -> &'static str { env!("CARGO_PKG_VERSION") }
+/// }
+///
+/// register_plugin!(Metrics);
+/// ```
+#[macro_export]
+macro_rules! register_plugin {
+    ($plugin:expr) => {
+        $crate::plugin::inventory::submit! {
+            $crate::plugin::PluginRegistration::new(&$plugin)
+        }
+    };
+}
+
+/// Every plugin registered in the binary, in no particular order
+pub fn registered() -> Vec<&'static dyn Plugin> {
+    inventory::iter::<PluginRegistration>
+        .into_iter()
+        .map(|registration| registration.plugin)
+        .collect()
+}
+
+/// Order plugins so that every plugin comes after its dependencies
+///
+/// Ties are broken by name so the order is deterministic.
+pub fn resolve(plugins: &[&'static dyn Plugin]) -> Result<Vec<&'static dyn Plugin>> {
+    let mut by_name = BTreeMap::new();
+    for &plugin in plugins {
+        if by_name.insert(plugin.name(), plugin).is_some() {
+            return Err(Error::Plugin(format!(
+                "plugin {} is registered more than once",
+                plugin.name()
+            )));
+        }
+    }
+    for plugin in by_name.values() {
+        if let Some(missing) = plugin
+            .dependencies()
+            .iter()
+            .find(|dep| !by_name.contains_key(*dep))
+        {
+            return Err(Error::Plugin(format!(
+                "plugin {} depends on unknown plugin {missing}",
+                plugin.name()
+            )));
+        }
+    }
+
+    let mut ordered = Vec::with_capacity(by_name.len());
+    let mut done = BTreeSet::new();
+    while ordered.len() < by_name.len() {
+        let ready: Vec<_> = by_name
+            .values()
+            .filter(|plugin| !done.contains(plugin.name()))
+            .filter(|plugin| plugin.dependencies().iter().all(|dep| done.contains(dep)))
+            .copied()
+            .collect();
+        if ready.is_empty() {
+            let cycle: Vec<_> = by_name
+                .keys()
+                .filter(|name| !done.contains(*name))
+                .copied()
+                .collect();
+            return Err(Error::Plugin(format!(
+                "dependency cycle between plugins: {}",
+                cycle.join(", ")
+            )));
+        }
+        for plugin in ready {
+            done.insert(plugin.name());
+            ordered.push(plugin);
+        }
+    }
+    Ok(ordered)
+}
+
+/// Set of initialized plugins, shut down in reverse initialization order
+#[derive(Default)]
+pub struct PluginHost {
+    initialized: Vec<&'static dyn Plugin>,
+}
+
+impl std::fmt::Debug for PluginHost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.initialized.iter().map(|plugin| plugin.name()))
+            .finish()
+    }
+}
+
+impl PluginHost {
+    /// Resolve and initialize `plugins`
+    ///
+    /// If a plugin fails to initialize, the ones already initialized are shut down again.
+    pub fn start(plugins: &[&'static dyn Plugin]) -> Result<Self> {
+        let mut host = Self::default();
+        for plugin in resolve(plugins)? {
+            if let Err(e) = plugin.init() {
+                let _ = host.shutdown();
+                return Err(Error::Plugin(format!(
+                    "plugin {} failed to initialize: {e}",
+                    plugin.name()
+                )));
+            }
+            tracing::debug!(
+                plugin = plugin.name(),
+                version = plugin.version(),
+                "plugin initialized"
+            );
+            host.initialized.push(plugin);
+        }
+        Ok(host)
+    }
+
+    /// Initialized plugins in initialization order
+    pub fn plugins(&self) -> &[&'static dyn Plugin] {
+        &self.initialized
+    }
+
+    /// Worst health of the initialized plugins, naming those that are not healthy
+    pub fn health(&self) -> CheckResult {
+        let results: Vec<_> = self
+            .initialized
+            .iter()
+            .map(|plugin| (plugin.name(), plugin.health()))
+            .collect();
+        let Some(status) = results.iter().map(|(_, result)| result.status).max() else {
+            return CheckResult::healthy("no plugins");
+        };
+        let details = if status == HealthStatus::Healthy {
+            format!("{} running", results.len())
+        } else {
+            results
+                .iter()
+                .filter(|(_, result)| result.status != HealthStatus::Healthy)
+                .map(|(name, result)| format!("{name}: {}", result.details))
+                .collect::<Vec<_>>()
+                .join("; ")
+        };
+        CheckResult { status, details }
+    }
+
+    /// Shut down every plugin in reverse order, reporting the first failure
+    pub fn shutdown(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        while let Some(plugin) = self.initialized.pop() {
+            if let Err(e) = plugin.shutdown() {
+                tracing::error!(plugin = plugin.name(), error = %e, "plugin shutdown failed");
+                if result.is_ok() {
+                    result = Err(Error::Plugin(format!(
+                        "plugin {} failed to shut down: {e}",
+                        plugin.name()
+                    )));
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    static EVENTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    struct TestPlugin {
+        name: &'static str,
+        dependencies: &'static [&'static str],
+        fail: bool,
+    }
+
+    impl Plugin for TestPlugin {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn version(&self) -> &'static str {
+            "0.1.0"
+        }
+
+        fn dependencies(&self) -> &'static [&'static str] {
+            self.dependencies
+        }
+
+        fn init(&self) -> Result<()> {
+            if self.fail {
+                return Err(Error::Other("boom".to_string()));
+            }
+            EVENTS.lock().unwrap().push(format!("init {}", self.name));
+            Ok(())
+        }
+
+        fn shutdown(&self) -> Result<()> {
+            EVENTS
+                .lock()
+                .unwrap()
+                .push(format!("shutdown {}", self.name));
+            Ok(())
+        }
+    }
+
+    const fn plugin(name: &'static str, dependencies: &'static [&'static str]) -> TestPlugin {
+        TestPlugin {
+            name,
+            dependencies,
+            fail: false,
+        }
+    }
+
+    static STORAGE: TestPlugin = plugin("storage", &[]);
+    static CACHE: TestPlugin = plugin("cache", &["storage"]);
+    static API: TestPlugin = plugin("api", &["cache", "storage"]);
+    static LOOP_A: TestPlugin = plugin("a", &["b"]);
+    static LOOP_B: TestPlugin = plugin("b", &["a"]);
+    static ORPHAN: TestPlugin = plugin("orphan", &["missing"]);
+    static BROKEN: TestPlugin = TestPlugin {
+        name: "broken",
+        dependencies: &["storage"],
+        fail: true,
+    };
+
+    struct RegisteredPlugin;
+
+    impl Plugin for RegisteredPlugin {
+        fn name(&self) -> &'static str {
+            "registered"
+        }
+
+        fn version(&self) -> &'static str {
+            "0.1.0"
+        }
+    }
+
+    crate::register_plugin!(RegisteredPlugin);
+
+    struct LaggingPlugin;
+
+    impl Plugin for LaggingPlugin {
+        fn name(&self) -> &'static str {
+            "lagging"
+        }
+
+        fn version(&self) -> &'static str {
+            "0.1.0"
+        }
+
+        fn health(&self) -> CheckResult {
+            CheckResult::degraded("replica behind")
+        }
+    }
+
+    fn names(plugins: &[&'static dyn Plugin]) -> Vec<&'static str> {
+        plugins.iter().map(|plugin| plugin.name()).collect()
+    }
+
+    #[test]
+    fn test_resolve_orders_by_dependencies() {
+        let ordered = resolve(&[&API, &STORAGE, &CACHE]).unwrap();
+        assert_eq!(names(&ordered), ["storage", "cache", "api"]);
+    }
+
+    #[test]
+    fn test_resolve_rejects_cycles_and_missing_dependencies() {
+        assert!(matches!(
+            resolve(&[&LOOP_A, &LOOP_B]),
+            Err(Error::Plugin(_))
+        ));
+        assert!(matches!(resolve(&[&ORPHAN]), Err(Error::Plugin(_))));
+        assert!(matches!(
+            resolve(&[&STORAGE, &STORAGE]),
+            Err(Error::Plugin(_))
+        ));
+    }
+
+    #[test]
+    fn test_failed_init_rolls_back() {
+        EVENTS.lock().unwrap().clear();
+        assert!(PluginHost::start(&[&BROKEN, &STORAGE]).is_err());
+        assert_eq!(
+            *EVENTS.lock().unwrap(),
+            ["init storage", "shutdown storage"]
+        );
+    }
+
+    #[test]
+    fn test_health_reports_worst_plugin() {
+        assert_eq!(PluginHost::default().health().details, "no plugins");
+
+        // Built directly so no init hooks run
+        let host = PluginHost {
+            initialized: vec![&RegisteredPlugin, &LaggingPlugin],
+        };
+        assert_eq!(
+            host.health(),
+            CheckResult::degraded("lagging: replica behind")
+        );
+    }
+
+    #[test]
+    fn test_registered_plugins_are_discovered() {
+        assert!(names(&registered()).contains(&"registered"));
+    }
+}
@@ -0,0 +1,176 @@
+//! Hot-reload of configuration from a watched file
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+use crate::config::{ConfigLoader, FieldChange};
+use crate::types::AppState;
+use crate::{Error, Result};
+
+/// Reload configuration through `loader` and swap it into `state`
+///
+/// On error the current configuration stays in effect.
+pub fn reload(state: &AppState, loader: &ConfigLoader) -> Result<Vec<FieldChange>> {
+    let loaded = loader.load()?;
+    state.replace_config(loaded.config)
+}
+
+/// Background watcher that reloads configuration when its file changes
+///
+/// The watcher stops when dropped.
+#[derive(Debug)]
+pub struct ConfigWatcher {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ConfigWatcher {
+    /// Default interval between checks of the configuration file
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// Watch the file of `loader` and reload into `state` whenever it changes
+    pub fn spawn(state: Arc<AppState>, loader: ConfigLoader, interval: Duration) -> Result<Self> {
+        let mut poller = FilePoller::new(&loader)?;
+        let (stop, stopped) = mpsc::channel();
+
+        let handle = thread::Builder::new()
+            .name("config-watcher".to_string())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    poller.poll(&state, &loader);
+                }
+            })?;
+
+        Ok(Self {
+            stop: Some(stop),
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for ConfigWatcher {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Configuration file checked for changes on every poll
+#[derive(Debug)]
+struct FilePoller {
+    path: PathBuf,
+    last: Option<(SystemTime, u64)>,
+}
+
+impl FilePoller {
+    /// Start from the current state of the file of `loader`
+    fn new(loader: &ConfigLoader) -> Result<Self> {
+        let path = loader
+            .file_path()
+            .ok_or_else(|| Error::Config("no configuration file to watch".to_string()))?
+            .to_path_buf();
+        let last = fingerprint(&path);
+        Ok(Self { path, last })
+    }
+
+    /// Reload into `state` if the file changed since the last poll
+    ///
+    /// Returns `None` when the file is unchanged.
+    fn poll(
+        &mut self,
+        state: &AppState,
+        loader: &ConfigLoader,
+    ) -> Option<Result<Vec<FieldChange>>> {
+        let current = fingerprint(&self.path);
+        if current == self.last {
+            return None;
+        }
+        self.last = current;
+        let result = reload(state, loader);
+        match &result {
+            Ok(changes) => tracing::info!(
+                path = %self.path.display(),
+                changed = changes.len(),
+                "configuration reloaded"
+            ),
+            Err(e) => tracing::error!(
+                path = %self.path.display(),
+                error = %e,
+                "configuration reload failed, keeping previous configuration"
+            ),
+        }
+        Some(result)
+    }
+}
+
+/// Cheap change detector based on modification time and size
+fn fingerprint(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Config;
+
+    #[test]
+    fn test_watcher_reloads_on_change() {
+        let file = tempfile::Builder::new().suffix(".toml").tempfile().unwrap();
+        std::fs::write(file.path(), "max_concurrent = 2\n").unwrap();
+        let loader = ConfigLoader::new().file(file.path());
+        let state = Arc::new(AppState::new(loader.load().unwrap().config));
+        let changes = state.subscribe();
+
+        let _watcher =
+            ConfigWatcher::spawn(Arc::clone(&state), loader, Duration::from_millis(10)).unwrap();
+
+        std::fs::write(file.path(), "max_concurrent = 16\n").unwrap();
+        let change = changes.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(change.changes[0].path, "max_concurrent");
+        assert_eq!(state.config().max_concurrent, 16);
+    }
+
+    #[test]
+    fn test_poll_keeps_old_config_on_invalid() {
+        let file = tempfile::Builder::new().suffix(".toml").tempfile().unwrap();
+        std::fs::write(file.path(), "max_concurrent = 2\n").unwrap();
+        let loader = ConfigLoader::new().file(file.path());
+        let state = AppState::new(loader.load().unwrap().config);
+        let mut poller = FilePoller::new(&loader).unwrap();
+        assert!(poller.poll(&state, &loader).is_none());
+
+        // Sizes differ, so each write is seen even within one mtime tick
+        std::fs::write(file.path(), "max_concurrent = 16\n").unwrap();
+        let changes = poller.poll(&state, &loader).unwrap().unwrap();
+        assert_eq!(changes[0].path, "max_concurrent");
+
+        std::fs::write(file.path(), "max_concurrent = 0 # invalid\n").unwrap();
+        assert!(poller.poll(&state, &loader).unwrap().is_err());
+        assert!(poller.poll(&state, &loader).is_none());
+        assert_eq!(state.config().max_concurrent, 16);
+        assert_ne!(*state.config(), Config::default());
+    }
+
+    #[test]
+    fn test_reload_rotates_secrets() {
+        let file = tempfile::Builder::new().suffix(".toml").tempfile().unwrap();
+        std::fs::write(file.path(), "api_token = \"first\"\n").unwrap();
+        let loader = ConfigLoader::new().file(file.path());
+        let state = AppState::new(loader.load().unwrap().config);
+        let changes = state.subscribe();
+
+        std::fs::write(file.path(), "api_token = \"second\"\n").unwrap();
+        let changed = reload(&state, &loader).unwrap();
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0].path, "api_token");
+        assert_eq!(changes.try_recv().unwrap().changes, changed);
+        let config = state.config();
+        assert_eq!(config.api_token.as_ref().unwrap().expose(), "second");
+    }
+}
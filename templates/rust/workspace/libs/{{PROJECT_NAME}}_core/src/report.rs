@@ -0,0 +1,293 @@
+//! Human-readable and JSON error reports
+//!
+//! An [`ErrorReport`] captures the full `source()` chain of an error, its
+//! stable code and category when it is a crate [`Error`], and a backtrace
+//! when `RUST_BACKTRACE` (or `RUST_LIB_BACKTRACE`) is set.
+
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+use serde_json::{Value, json};
+
+use crate::Error;
+use crate::error::{ErrorCategory, Violation};
+
+/// Output format for error reports
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// Multi-line text for terminals
+    #[default]
+    Human,
+    /// Single-line JSON object for log pipelines
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> crate::Result<Self> {
+        match s {
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            _ => Err(Error::Usage(format!(
+                "unknown error format {s:?}, expected \"human\" or \"json\""
+            ))),
+        }
+    }
+}
+
+/// A single frame of a captured backtrace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Demangled symbol name
+    pub symbol: String,
+    /// Source location as `file:line:column`, if known
+    pub location: Option<String>,
+}
+
+/// Error with its cause chain, classification and optional backtrace
+#[derive(Debug, Clone)]
+pub struct ErrorReport {
+    /// Stable error code, for crate errors
+    pub code: Option<&'static str>,
+    /// Error category; unknown errors are reported as internal
+    pub category: ErrorCategory,
+    /// Top-level error message
+    pub message: String,
+    /// Messages of the `source()` chain, outermost first
+    ///
+    /// A cause whose message already ends the message before it is left out.
+    pub causes: Vec<String>,
+    /// Configuration violations, for validation errors
+    pub violations: Vec<Violation>,
+    /// Captured backtrace frames, empty unless backtraces are enabled
+    pub backtrace: Vec<Frame>,
+}
+
+impl ErrorReport {
+    /// Build a report from any error, capturing a backtrace here if enabled
+    pub fn new(error: &(dyn std::error::Error + 'static)) -> Self {
+        let mut report = Self::from_chain(std::iter::successors(Some(error), |e| e.source()));
+        report.backtrace = parse_backtrace(&Backtrace::capture());
+        report
+    }
+
+    /// Build a report from an `anyhow` error, using the backtrace captured at its creation
+    pub fn from_anyhow(error: &anyhow::Error) -> Self {
+        let mut report = Self::from_chain(error.chain());
+        report.backtrace = parse_backtrace(error.backtrace());
+        report
+    }
+
+    fn from_chain<'a>(
+        mut chain: impl Iterator<Item = &'a (dyn std::error::Error + 'static)>,
+    ) -> Self {
+        let mut report = Self {
+            code: None,
+            category: ErrorCategory::Internal,
+            message: String::new(),
+            causes: Vec::new(),
+            violations: Vec::new(),
+            backtrace: Vec::new(),
+        };
+        if let Some(top) = chain.next() {
+            report.message = top.to_string();
+            report.classify(top);
+        }
+        let mut previous = report.message.clone();
+        for cause in chain {
+            if report.code.is_none() {
+                report.classify(cause);
+            }
+            // Errors such as `Error::Io` embed their source in their own message
+            let message = cause.to_string();
+            if !previous.ends_with(&message) {
+                report.causes.push(message.clone());
+            }
+            previous = message;
+        }
+        report
+    }
+
+    /// Take code and category from the first crate error in the chain
+    fn classify(&mut self, error: &(dyn std::error::Error + 'static)) {
+        if let Some(error) = error.downcast_ref::<Error>() {
+            self.code = Some(error.code());
+            self.category = error.category();
+            if let Error::Validation(violations) = error {
+                self.violations.clone_from(violations);
+            }
+        }
+    }
+
+    /// Process exit code for the reported error
+    pub fn exit_code(&self) -> u8 {
+        self.category.exit_code()
+    }
+
+    /// Render in the requested format
+    pub fn render(&self, format: ErrorFormat) -> String {
+        match format {
+            ErrorFormat::Human => self.to_string(),
+            ErrorFormat::Json => self.to_json().to_string(),
+        }
+    }
+
+    /// Machine-readable representation
+    pub fn to_json(&self) -> Value {
+        let violations: Vec<Value> = self
+            .violations
+            .iter()
+            .map(|v| {
+                json!({
+                    "path": v.path,
+                    "value": v.value,
+                    "constraint": v.constraint,
+                    "hint": v.hint,
+                })
+            })
+            .collect();
+        let backtrace: Vec<Value> = self
+            .backtrace
+            .iter()
+            .map(|frame| json!({ "symbol": frame.symbol, "location": frame.location }))
+            .collect();
+        json!({
+            "code": self.code,
+            "category": self.category.as_str(),
+            "exit_code": self.exit_code(),
+            "message": self.message,
+            "causes": self.causes,
+            "violations": violations,
+            "backtrace": backtrace,
+        })
+    }
+}
+
+impl fmt::Display for ErrorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.code {
+            Some(code) => writeln!(f, "Error [{code}]: {}", self.message)?,
+            None => writeln!(f, "Error: {}", self.message)?,
+        }
+        for cause in &self.causes {
+            writeln!(f, "  caused by: {cause}")?;
+        }
+        if !self.violations.is_empty() {
+            f.write_str(&render_violations(&self.violations))?;
+        }
+        if !self.backtrace.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Stack backtrace:")?;
+            for (index, frame) in self.backtrace.iter().enumerate() {
+                writeln!(f, "{index:>4}: {}", frame.symbol)?;
+                if let Some(location) = &frame.location {
+                    writeln!(f, "             at {location}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Render violations grouped by the section they belong to
+fn render_violations(violations: &[Violation]) -> String {
+    let mut sections: BTreeMap<&str, Vec<&Violation>> = BTreeMap::new();
+    for violation in violations {
+        let section = violation
+            .path
+            .rsplit_once('.')
+            .map_or("(root)", |(section, _)| section);
+        sections.entry(section).or_default().push(violation);
+    }
+
+    let mut out = String::new();
+    for (section, violations) in sections {
+        let _ = writeln!(out, "\n[{section}]");
+        for violation in violations {
+            let _ = writeln!(out, "  {} = {}", violation.path, violation.value);
+            let _ = writeln!(out, "    constraint: {}", violation.constraint);
+            let _ = writeln!(out, "    hint: {}", violation.hint);
+        }
+    }
+    out
+}
+
+/// Split the text form of a captured backtrace into frames
+fn parse_backtrace(backtrace: &Backtrace) -> Vec<Frame> {
+    if backtrace.status() != BacktraceStatus::Captured {
+        return Vec::new();
+    }
+    let mut frames: Vec<Frame> = Vec::new();
+    for line in backtrace.to_string().lines() {
+        let line = line.trim();
+        if let Some(location) = line.strip_prefix("at ") {
+            if let Some(frame) = frames.last_mut() {
+                frame.location = Some(location.to_string());
+            }
+            continue;
+        }
+        match line.split_once(": ") {
+            Some((index, symbol)) if index.chars().all(|c| c.is_ascii_digit()) => {
+                frames.push(Frame {
+                    symbol: symbol.to_string(),
+                    location: None,
+                });
+            }
+            _ => {}
+        }
+    }
+    frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_walks_cause_chain() {
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "config.toml missing");
+        let error = anyhow::Error::from(Error::from(io)).context("cannot start");
+        let report = ErrorReport::from_anyhow(&error);
+
+        assert_eq!(report.message, "cannot start");
+        assert_eq!(report.causes, ["IO error: config.toml missing"]);
+        assert_eq!(report.code, Some("E0001"));
+        assert_eq!(report.exit_code(), 74);
+
+        let human = report.render(ErrorFormat::Human);
+        assert!(human.starts_with("Error [E0001]: cannot start\n"));
+        assert!(human.contains("  caused by: IO error: config.toml missing\n"));
+        assert!(!human.contains("  caused by: config.toml missing"));
+    }
+
+    #[test]
+    fn test_json_report() {
+        let error = Error::Validation(vec![Violation::new(
+            "max_concurrent",
+            0,
+            "must be at least 1",
+            "use 1",
+        )]);
+        let report = ErrorReport::new(&error);
+        let json: Value = serde_json::from_str(&report.render(ErrorFormat::Json)).unwrap();
+
+        assert_eq!(json["code"], "E0003");
+        assert_eq!(json["category"], "config");
+        assert_eq!(json["exit_code"], 78);
+        assert_eq!(json["violations"][0]["path"], "max_concurrent");
+        assert!(json["causes"].as_array().unwrap().is_empty());
+        assert!(json["backtrace"].is_array());
+    }
+
+    #[test]
+    fn test_unknown_errors_are_internal() {
+        let report = ErrorReport::from_anyhow(&anyhow::anyhow!("boom"));
+        assert_eq!(report.code, None);
+        assert_eq!(report.category, ErrorCategory::Internal);
+        assert_eq!("json".parse::<ErrorFormat>().unwrap(), ErrorFormat::Json);
+        assert!("xml".parse::<ErrorFormat>().is_err());
+    }
+}
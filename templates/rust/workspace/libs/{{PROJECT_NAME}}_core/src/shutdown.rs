@@ -0,0 +1,237 @@
+//! Graceful shutdown coordination
+//!
+//! The [`ShutdownCoordinator`] turns SIGINT/SIGTERM (or a programmatic request)
+//! into cancellation of every registered operation, waits for them to finish
+//! within a grace period and then runs cleanup hooks in reverse registration
+//! order. A second signal exits the process immediately.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::events::Event;
+use crate::operations::{CancellationToken, OperationRegistry};
+use crate::{Error, Result};
+
+/// Exit code after a graceful shutdown triggered by a signal or request
+pub const EXIT_INTERRUPTED: i32 = 130;
+/// Exit code when operations were still running after the grace period
+pub const EXIT_GRACE_EXPIRED: i32 = 124;
+/// Exit code when a second signal forced an immediate exit
+pub const EXIT_FORCED: i32 = 137;
+
+/// Interval between checks for remaining operations while draining
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+type CleanupHook = Box<dyn FnOnce() + Send>;
+
+/// How a shutdown ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownOutcome {
+    /// No shutdown was requested; the program finished normally
+    Completed,
+    /// Shutdown was requested and all operations finished in time
+    Interrupted,
+    /// Shutdown was requested but operations were still running after the grace period
+    GraceExpired {
+        /// Operations still running when the grace period ended
+        remaining: usize,
+    },
+}
+
+impl ShutdownOutcome {
+    /// Process exit code for this outcome
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Completed => 0,
+            Self::Interrupted => EXIT_INTERRUPTED,
+            Self::GraceExpired { .. } => EXIT_GRACE_EXPIRED,
+        }
+    }
+}
+
+struct Inner {
+    operations: OperationRegistry,
+    grace_period: Duration,
+    requests: AtomicUsize,
+    token: CancellationToken,
+    hooks: Mutex<Vec<(String, CleanupHook)>>,
+}
+
+impl Inner {
+    fn hooks(&self) -> MutexGuard<'_, Vec<(String, CleanupHook)>> {
+        self.hooks.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Record a request and cancel running operations; returns how many came before
+    fn request(&self) -> usize {
+        let previous = self.requests.fetch_add(1, Ordering::SeqCst);
+        if previous == 0 {
+            self.operations.events().publish(Event::ShutdownRequested);
+        }
+        self.token.cancel();
+        self.operations.cancel_all();
+        previous
+    }
+}
+
+/// Coordinates cancellation, draining and cleanup when the program shuts down
+#[derive(Clone)]
+pub struct ShutdownCoordinator {
+    inner: Arc<Inner>,
+}
+
+impl std::fmt::Debug for ShutdownCoordinator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShutdownCoordinator")
+            .field("grace_period", &self.inner.grace_period)
+            .field("requested", &self.is_shutdown_requested())
+            .field("hooks", &self.inner.hooks().len())
+            .finish()
+    }
+}
+
+impl ShutdownCoordinator {
+    /// Create a coordinator that cancels and drains `operations`
+    pub fn new(operations: OperationRegistry, grace_period: Duration) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                operations,
+                grace_period,
+                requests: AtomicUsize::new(0),
+                token: CancellationToken::new(),
+                hooks: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Register a cleanup hook; hooks run in reverse registration order
+    pub fn on_cleanup(&self, name: impl Into<String>, hook: impl FnOnce() + Send + 'static) {
+        self.inner.hooks().push((name.into(), Box::new(hook)));
+    }
+
+    /// React to SIGINT and SIGTERM; a second signal exits with [`EXIT_FORCED`]
+    ///
+    /// The first signal prints a notice on stderr. Can only be installed once
+    /// per process.
+    pub fn install_signal_handlers(&self) -> Result<()> {
+        let inner = Arc::clone(&self.inner);
+        ctrlc::set_handler(move || {
+            if inner.request() > 0 {
+                std::process::exit(EXIT_FORCED);
+            }
+            eprintln!("Shutting down, press Ctrl-C again to force exit");
+        })
+        .map_err(|e| Error::Other(format!("cannot install signal handlers: {e}")))
+    }
+
+    /// Request shutdown and cancel all running operations
+    pub fn request_shutdown(&self) {
+        self.inner.request();
+    }
+
+    /// Token cancelled once shutdown is requested, for work that is not a registered operation
+    pub fn token(&self) -> CancellationToken {
+        self.inner.token.clone()
+    }
+
+    /// Whether shutdown has been requested
+    pub fn is_shutdown_requested(&self) -> bool {
+        self.inner.requests.load(Ordering::SeqCst) > 0
+    }
+
+    /// Drain running operations if shutdown was requested, then run cleanup hooks
+    pub fn shutdown(&self) -> ShutdownOutcome {
+        // A grace period beyond what an `Instant` can represent never expires
+        self.shutdown_until(Instant::now().checked_add(self.inner.grace_period))
+    }
+
+    /// Like [`shutdown`](Self::shutdown), but drain operations only until `deadline`
+    ///
+    /// For callers that already spent part of the grace period; `None` waits
+    /// for the operations however long they take.
+    pub fn shutdown_until(&self, deadline: Option<Instant>) -> ShutdownOutcome {
+        let outcome = if self.is_shutdown_requested() {
+            self.inner.operations.cancel_all();
+            while !self.inner.operations.is_empty()
+                && deadline.is_none_or(|deadline| Instant::now() < deadline)
+            {
+                thread::sleep(DRAIN_POLL_INTERVAL);
+            }
+            match self.inner.operations.len() {
+                0 => ShutdownOutcome::Interrupted,
+                remaining => ShutdownOutcome::GraceExpired { remaining },
+            }
+        } else {
+            ShutdownOutcome::Completed
+        };
+
+        let hooks = std::mem::take(&mut *self.inner.hooks());
+        for (name, hook) in hooks.into_iter().rev() {
+            tracing::debug!(hook = %name, "running cleanup hook");
+            hook();
+        }
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hooks_run_in_reverse_order() {
+        let coordinator = ShutdownCoordinator::new(OperationRegistry::new(), Duration::ZERO);
+        let order = Arc::new(Mutex::new(Vec::new()));
+        for name in ["first", "second", "third"] {
+            let order = Arc::clone(&order);
+            coordinator.on_cleanup(name, move || order.lock().unwrap().push(name));
+        }
+
+        assert_eq!(coordinator.shutdown(), ShutdownOutcome::Completed);
+        assert_eq!(*order.lock().unwrap(), ["third", "second", "first"]);
+    }
+
+    #[test]
+    fn test_request_cancels_and_drains_operations() {
+        let operations = OperationRegistry::new();
+        let events = operations.events().subscribe(Default::default());
+        // An unbounded grace period waits for the worker without overflowing
+        let coordinator = ShutdownCoordinator::new(operations.clone(), Duration::MAX);
+        let operation = operations.register("worker");
+        let worker = thread::spawn(move || {
+            while !operation.is_cancelled() {
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+
+        let token = coordinator.token();
+        assert!(!token.is_cancelled());
+        coordinator.request_shutdown();
+        coordinator.request_shutdown();
+        assert!(token.is_cancelled());
+        let outcome = coordinator.shutdown();
+        worker.join().unwrap();
+        assert_eq!(outcome, ShutdownOutcome::Interrupted);
+        let requested = events
+            .try_iter()
+            .filter(|event| matches!(event, Event::ShutdownRequested))
+            .count();
+        assert_eq!(requested, 1);
+        assert_eq!(outcome.exit_code(), EXIT_INTERRUPTED);
+    }
+
+    #[test]
+    fn test_grace_period_expires() {
+        let operations = OperationRegistry::new();
+        let coordinator = ShutdownCoordinator::new(operations.clone(), Duration::from_millis(20));
+        let _stuck = operations.register("stuck");
+
+        coordinator.request_shutdown();
+        assert_eq!(
+            coordinator.shutdown(),
+            ShutdownOutcome::GraceExpired { remaining: 1 }
+        );
+    }
+}
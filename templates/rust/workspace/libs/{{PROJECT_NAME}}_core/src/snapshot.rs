@@ -0,0 +1,286 @@
+//! Versioned on-disk snapshots of [`AppState`]
+//!
+//! A snapshot file holds a format version next to the state. Older versions are
+//! upgraded through [`MIGRATIONS`] when loaded. A file that cannot be parsed is
+//! moved aside with a `.corrupt-<timestamp>` suffix so startup can continue
+//! with fresh state.
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::metrics::MetricsSnapshot;
+use crate::operations::History;
+use crate::types::{AppState, Config};
+use crate::{Error, Result};
+
+/// Version written by [`SnapshotStore::save`]
+pub const CURRENT_VERSION: u64 = 1;
+
+/// Upgrade of the `state` value of a snapshot by one version
+pub type Migration = fn(Value) -> std::result::Result<Value, String>;
+
+/// Migration chain; entry `i` upgrades version `i + 1` to `i + 2`
+///
+/// Append an entry and bump [`CURRENT_VERSION`] whenever [`Snapshot`] changes
+/// incompatibly. Never edit or remove existing entries.
+pub const MIGRATIONS: &[Migration] = &[];
+
+const _: () = assert!(MIGRATIONS.len() as u64 + 1 == CURRENT_VERSION);
+
+/// Persisted part of [`AppState`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Snapshot {
+    /// Counters and recent history of finished operations
+    pub operations: History,
+    /// Counters and histograms; gauges are not restored
+    pub metrics: MetricsSnapshot,
+}
+
+impl AppState {
+    /// Capture the state that survives restarts
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            operations: self.operations().history(),
+            metrics: self.metrics().snapshot(),
+        }
+    }
+
+    /// Restore state captured by [`AppState::snapshot`]
+    pub fn restore(&self, snapshot: Snapshot) {
+        self.operations().restore_history(snapshot.operations);
+        self.metrics().restore(&snapshot.metrics);
+    }
+}
+
+/// Snapshot file on disk
+#[derive(Debug, Clone)]
+pub struct SnapshotStore {
+    path: PathBuf,
+}
+
+impl SnapshotStore {
+    /// Name of the snapshot file inside the data directory
+    pub const FILE_NAME: &'static str = "state.json";
+
+    /// Store snapshots at `path`
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Store snapshots in `Config::data_dir`, or the platform data directory
+    pub fn for_config(config: &Config) -> Result<Self> {
+        let dir = match &config.data_dir {
+            Some(dir) => PathBuf::from(dir),
+            None => dirs::data_local_dir()
+                .ok_or_else(|| Error::Config("no data directory on this platform".to_string()))?
+                .join("{{PROJECT_NAME}}"),
+        };
+        Ok(Self::new(dir.join(Self::FILE_NAME)))
+    }
+
+    /// Location of the snapshot file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Load and migrate the snapshot, or `None` if there is none
+    ///
+    /// A corrupt file, including one that is not valid UTF-8, is quarantined
+    /// and treated as missing. A file written by
+    /// a newer version is left untouched and reported as an error.
+    pub fn load(&self) -> Result<Option<Snapshot>> {
+        let contents = match fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        match decode(&contents, MIGRATIONS) {
+            Ok(snapshot) => Ok(Some(snapshot)),
+            Err(Decode::Newer(version)) => Err(Error::Snapshot(format!(
+                "{} has version {version}, newer than the supported version {CURRENT_VERSION}",
+                self.path.display()
+            ))),
+            Err(Decode::Corrupt(reason)) => {
+                let quarantined = self.quarantine()?;
+                tracing::warn!(
+                    path = %self.path.display(),
+                    quarantined = %quarantined.display(),
+                    %reason,
+                    "corrupt snapshot quarantined"
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// Write `snapshot` atomically, creating the data directory if needed
+    pub fn save(&self, snapshot: &Snapshot) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let document = json!({ "version": CURRENT_VERSION, "state": snapshot });
+        let contents =
+            serde_json::to_string_pretty(&document).map_err(|e| Error::Snapshot(e.to_string()))?;
+
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, contents)?;
+        fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+
+    /// Move the snapshot file aside and return its new location
+    fn quarantine(&self) -> Result<PathBuf> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut quarantined = self.path.clone().into_os_string();
+        quarantined.push(format!(".corrupt-{timestamp}"));
+        let quarantined = PathBuf::from(quarantined);
+        fs::rename(&self.path, &quarantined)?;
+        Ok(quarantined)
+    }
+}
+
+/// Why a snapshot file could not be decoded
+#[derive(Debug)]
+enum Decode {
+    Newer(u64),
+    Corrupt(String),
+}
+
+/// Parse a snapshot document and run the migrations it needs
+fn decode(contents: &[u8], migrations: &[Migration]) -> std::result::Result<Snapshot, Decode> {
+    let mut document: Value =
+        serde_json::from_slice(contents).map_err(|e| Decode::Corrupt(e.to_string()))?;
+    let version = document
+        .get("version")
+        .and_then(Value::as_u64)
+        .filter(|version| *version >= 1)
+        .ok_or_else(|| Decode::Corrupt("missing or invalid version".to_string()))?;
+    let current = migrations.len() as u64 + 1;
+    if version > current {
+        return Err(Decode::Newer(version));
+    }
+
+    let mut state = document
+        .get_mut("state")
+        .map(Value::take)
+        .ok_or_else(|| Decode::Corrupt("missing state".to_string()))?;
+    for (index, migrate) in migrations.iter().enumerate().skip(version as usize - 1) {
+        state = migrate(state)
+            .map_err(|e| Decode::Corrupt(format!("migration to version {}: {e}", index + 2)))?;
+    }
+    let snapshot: Snapshot =
+        serde_json::from_value(state).map_err(|e| Decode::Corrupt(e.to_string()))?;
+    snapshot
+        .metrics
+        .validate()
+        .map_err(|e| Decode::Corrupt(e.to_string()))?;
+    Ok(snapshot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::MetricKind;
+
+    #[test]
+    fn test_save_and_load_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SnapshotStore::for_config(&Config {
+            data_dir: Some(dir.path().join("nested").display().to_string()),
+            ..Config::default()
+        })
+        .unwrap();
+        assert!(store.load().unwrap().is_none());
+
+        let state = AppState::new(Config::default());
+        drop(state.start_operation("import"));
+        store.save(&state.snapshot()).unwrap();
+
+        let restored = AppState::new(Config::default());
+        restored.restore(store.load().unwrap().unwrap());
+        assert_eq!(restored.operations().history().counters["import"], 1);
+        assert_eq!(restored.snapshot(), state.snapshot());
+    }
+
+    #[test]
+    fn test_migrations_upgrade_old_versions() {
+        fn rename_history(mut state: Value) -> std::result::Result<Value, String> {
+            let history = state
+                .get_mut("history")
+                .map(Value::take)
+                .unwrap_or_default();
+            Ok(json!({ "operations": history }))
+        }
+        fn add_recent(mut state: Value) -> std::result::Result<Value, String> {
+            state["operations"]["recent"] = json!([]);
+            Ok(state)
+        }
+        let migrations: &[Migration] = &[rename_history, add_recent];
+
+        let v1 = br#"{"version": 1, "state": {"history": {"counters": {"run": 3}}}}"#;
+        let snapshot = decode(v1, migrations).unwrap();
+        assert_eq!(snapshot.operations.counters["run"], 3);
+
+        let v3 = br#"{"version": 3, "state": {"operations": {"counters": {"run": 5}}}}"#;
+        assert_eq!(decode(v3, migrations).unwrap().operations.total(), 5);
+        assert!(matches!(decode(v3, &[]), Err(Decode::Newer(3))));
+    }
+
+    #[test]
+    fn test_corrupt_file_is_quarantined() {
+        // Well-formed, but a counter family claims to be a gauge
+        let mismatched = {
+            let dir = tempfile::tempdir().unwrap();
+            let store = SnapshotStore::new(dir.path().join(SnapshotStore::FILE_NAME));
+            let state = AppState::new(Config::default());
+            state.metrics().counter("runs_total", "Runs", &[]).inc();
+            let mut snapshot = state.snapshot();
+            for family in &mut snapshot.metrics.families {
+                if family.name == "runs_total" {
+                    family.kind = MetricKind::Gauge;
+                }
+            }
+            store.save(&snapshot).unwrap();
+            fs::read(store.path()).unwrap()
+        };
+
+        for contents in [
+            &b"{ not json"[..],
+            b"{\"version\": 1, \"state\": \"\xff\"}",
+            &mismatched,
+        ] {
+            let dir = tempfile::tempdir().unwrap();
+            let store = SnapshotStore::new(dir.path().join(SnapshotStore::FILE_NAME));
+            fs::write(store.path(), contents).unwrap();
+
+            assert!(store.load().unwrap().is_none());
+            assert!(!store.path().exists());
+            let quarantined: Vec<_> = fs::read_dir(dir.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect();
+            assert_eq!(quarantined.len(), 1);
+            assert!(quarantined[0].starts_with("state.json.corrupt-"));
+        }
+    }
+
+    #[test]
+    fn test_newer_version_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SnapshotStore::new(dir.path().join(SnapshotStore::FILE_NAME));
+        fs::write(store.path(), r#"{"version": 99, "state": {}}"#).unwrap();
+
+        assert!(matches!(store.load(), Err(Error::Snapshot(_))));
+        assert!(store.path().exists());
+    }
+}
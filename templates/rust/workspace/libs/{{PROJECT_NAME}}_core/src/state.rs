@@ -0,0 +1,287 @@
+//! Shared application state
+
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::Duration;
+
+use crate::Result;
+use crate::config::{FieldChange, diff};
+use crate::events::{Event, EventBus};
+use crate::flags::{FeatureFlags, Flag, FlagState};
+use crate::health::{HealthRegistry, HealthReport};
+use crate::limiter::{Acquire, ConcurrencyLimiter, OperationPermit};
+use crate::metrics::MetricsRegistry;
+use crate::operations::{Operation, OperationRegistry};
+use crate::types::Config;
+
+/// Application state
+#[derive(Debug)]
+pub struct AppState {
+    /// Current configuration, swapped atomically on reload
+    config: RwLock<Arc<Config>>,
+    /// Listeners notified when the configuration changes
+    subscribers: Mutex<Vec<Sender<ConfigChange>>>,
+    /// Limiter enforcing `max_concurrent`
+    limiter: ConcurrencyLimiter,
+    /// Operations currently running
+    operations: OperationRegistry,
+    /// Built-in and application metrics
+    metrics: MetricsRegistry,
+    /// Checks behind [`AppState::check_health`]
+    health: HealthRegistry,
+    /// Declared feature flags and their overrides
+    flags: FeatureFlags,
+}
+
+/// Notification sent to subscribers after the configuration changed
+#[derive(Debug, Clone)]
+pub struct ConfigChange {
+    /// Configuration now in effect
+    pub config: Arc<Config>,
+    /// Fields that changed
+    pub changes: Vec<FieldChange>,
+}
+
+impl AppState {
+    /// Create new application state with config
+    pub fn new(config: Config) -> Self {
+        Self::with_events(config, EventBus::new())
+    }
+
+    /// Create application state publishing lifecycle events on `events`
+    ///
+    /// Subscribe to `events` beforehand to also receive the initial
+    /// [`Event::ConfigLoaded`].
+    pub fn with_events(config: Config, events: EventBus) -> Self {
+        let config = Arc::new(config);
+        let metrics = MetricsRegistry::new();
+        metrics.instrument(&events);
+        events.publish(Event::ConfigLoaded {
+            config: Arc::clone(&config),
+            changes: Vec::new(),
+        });
+        Self {
+            limiter: ConcurrencyLimiter::new(config.max_concurrent),
+            config: RwLock::new(config),
+            subscribers: Mutex::new(Vec::new()),
+            operations: OperationRegistry::with_events(events),
+            metrics,
+            health: HealthRegistry::with_builtin_checks(),
+            flags: FeatureFlags::registered(),
+        }
+    }
+
+    /// Use `flags` instead of the registered flags without overrides
+    pub fn with_flags(mut self, flags: FeatureFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Effective setting of `flag` under the current configuration
+    pub fn flag(&self, flag: &'static Flag) -> FlagState {
+        self.flags.state(flag, &self.config())
+    }
+
+    /// Effective setting of every known flag, sorted by name
+    pub fn flags(&self) -> Vec<FlagState> {
+        self.flags.states(&self.config())
+    }
+
+    /// Built-in and application metrics
+    pub fn metrics(&self) -> &MetricsRegistry {
+        &self.metrics
+    }
+
+    /// Registry to add health checks to
+    pub fn health(&self) -> &HealthRegistry {
+        &self.health
+    }
+
+    /// Run every registered health check
+    pub fn check_health(&self) -> HealthReport {
+        self.health.run(self)
+    }
+
+    /// Bus on which lifecycle events are published
+    pub fn events(&self) -> &EventBus {
+        self.operations.events()
+    }
+
+    /// Registry of running operations
+    pub fn operations(&self) -> &OperationRegistry {
+        &self.operations
+    }
+
+    /// Wait for a free slot, then register an operation that holds it until dropped
+    pub fn start_operation(&self, name: impl Into<String>) -> Operation {
+        let permit = self.acquire();
+        self.operations.register(name).with_permit(permit)
+    }
+
+    /// Wait asynchronously for a free slot, then register an operation holding it
+    pub async fn start_operation_async(&self, name: impl Into<String>) -> Operation {
+        let permit = self.acquire_async().await;
+        self.operations.register(name).with_permit(permit)
+    }
+
+    /// Number of operations currently holding a permit
+    pub fn active_operations(&self) -> usize {
+        self.limiter.active()
+    }
+
+    /// Block until an operation slot is free
+    pub fn acquire(&self) -> OperationPermit {
+        self.limiter.acquire()
+    }
+
+    /// Block until an operation slot is free or `timeout` elapses
+    pub fn try_acquire_for(&self, timeout: Duration) -> Option<OperationPermit> {
+        self.limiter.try_acquire_for(timeout)
+    }
+
+    /// Wait asynchronously until an operation slot is free
+    pub fn acquire_async(&self) -> Acquire {
+        self.limiter.acquire_async()
+    }
+
+    /// Wait asynchronously until an operation slot is free or `timeout` elapses
+    #[cfg(feature = "async")]
+    pub async fn try_acquire_async_for(&self, timeout: Duration) -> Option<OperationPermit> {
+        self.limiter.try_acquire_async_for(timeout).await
+    }
+
+    /// Snapshot of the configuration currently in effect
+    pub fn config(&self) -> Arc<Config> {
+        Arc::clone(&self.config.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Receive a [`ConfigChange`] every time the configuration changes
+    pub fn subscribe(&self) -> Receiver<ConfigChange> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(tx);
+        rx
+    }
+
+    /// Validate `config` and swap it in, notifying subscribers of changed fields
+    ///
+    /// An invalid configuration is rejected and the current one stays in effect.
+    pub fn replace_config(&self, config: Config) -> Result<Vec<FieldChange>> {
+        config.validate()?;
+        let config = Arc::new(config);
+        let previous = {
+            let mut current = self.config.write().unwrap_or_else(PoisonError::into_inner);
+            std::mem::replace(&mut *current, Arc::clone(&config))
+        };
+
+        self.limiter.set_capacity(config.max_concurrent);
+
+        let changes = diff(&previous, &config);
+        if !changes.is_empty() {
+            let change = ConfigChange {
+                config: Arc::clone(&config),
+                changes: changes.clone(),
+            };
+            self.subscribers
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .retain(|tx| tx.send(change.clone()).is_ok());
+            self.events().publish(Event::ConfigLoaded {
+                config,
+                changes: changes.clone(),
+            });
+        }
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replace_config_notifies_subscribers() {
+        let state = AppState::new(Config::default());
+        let changes = state.subscribe();
+
+        let invalid = Config {
+            max_concurrent: 0,
+            ..Config::default()
+        };
+        assert!(state.replace_config(invalid).is_err());
+        assert_eq!(state.config().max_concurrent, 4);
+
+        let updated = Config {
+            debug: true,
+            ..Config::default()
+        };
+        state.replace_config(updated).unwrap();
+        assert!(state.config().debug);
+
+        let change = changes.try_recv().unwrap();
+        assert_eq!(change.changes.len(), 1);
+        assert_eq!(change.changes[0].path, "debug");
+        assert!(changes.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_permits_follow_max_concurrent() {
+        let state = AppState::new(Config {
+            max_concurrent: 1,
+            ..Config::default()
+        });
+        let permit = state.acquire();
+        assert_eq!(state.active_operations(), 1);
+        assert!(state.try_acquire_for(Duration::ZERO).is_none());
+
+        state
+            .replace_config(Config {
+                max_concurrent: 2,
+                ..Config::default()
+            })
+            .unwrap();
+        let _second = state.try_acquire_for(Duration::ZERO).unwrap();
+        assert_eq!(state.active_operations(), 2);
+
+        drop(permit);
+        assert_eq!(state.active_operations(), 1);
+    }
+
+    #[test]
+    fn test_lifecycle_events_are_published() {
+        let events = EventBus::new();
+        let subscription = events.subscribe(Default::default());
+        let state = AppState::with_events(Config::default(), events);
+        drop(state.start_operation("index"));
+        state
+            .replace_config(Config {
+                debug: true,
+                ..Config::default()
+            })
+            .unwrap();
+
+        let received: Vec<_> = subscription
+            .try_iter()
+            .map(|event| event.to_string())
+            .collect();
+        assert_eq!(received.len(), 4);
+        assert_eq!(received[0], "configuration loaded");
+        assert_eq!(received[1], "op-1 index started");
+        assert!(received[2].starts_with("op-1 index finished"));
+        assert_eq!(received[3], "configuration loaded (1 changed)");
+    }
+
+    #[test]
+    fn test_start_operation_registers_and_holds_permit() {
+        let state = AppState::new(Config::default());
+        let operation = state.start_operation("index");
+        assert_eq!(state.active_operations(), 1);
+        assert_eq!(state.operations().list()[0].id, operation.id());
+
+        drop(operation);
+        assert_eq!(state.active_operations(), 0);
+        assert!(state.operations().is_empty());
+    }
+}
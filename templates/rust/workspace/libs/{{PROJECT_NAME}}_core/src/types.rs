@@ -1,14 +1,51 @@
 //! Common types used throughout the project
 
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::time::Duration;
+
+use secrecy::{ExposeSecret, SecretString};
+use serde::de::{self, Unexpected, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::error::{Error, Result, Violation};
+
+#[cfg(feature = "std")]
+pub use crate::state::{AppState, ConfigChange};
+
 /// Configuration structure
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     /// Application name
+    #[cfg_attr(
+        feature = "schema",
+        schemars(regex(pattern = r"^[A-Za-z][A-Za-z0-9_]*$"))
+    )]
     pub name: String,
     /// Debug mode enabled
     pub debug: bool,
     /// Maximum concurrent operations
+    #[cfg_attr(feature = "schema", schemars(range(min = 1)))]
     pub max_concurrent: usize,
+    /// Seconds to wait for running operations to finish on shutdown
+    #[cfg_attr(
+        feature = "schema",
+        schemars(range(max = Config::MAX_SHUTDOWN_GRACE_PERIOD_SECS))
+    )]
+    pub shutdown_grace_period_secs: u64,
+    /// Directory for persisted state; defaults to the platform data directory
+    pub data_dir: Option<String>,
+    /// Token for authenticating against remote APIs
+    pub api_token: Option<Secret>,
+    /// Feature flag settings by flag name, overriding the declared defaults
+    pub flags: BTreeMap<String, FlagSetting>,
+    /// Rate limits by name, e.g. `run` for calls made by run workloads
+    pub rate_limits: BTreeMap<String, RateLimit>,
 }
 
 impl Default for Config {
@@ -17,25 +54,395 @@ impl Default for Config {
             name: "{{PROJECT_NAME}}".to_string(),
             debug: false,
             max_concurrent: 4,
+            shutdown_grace_period_secs: 10,
+            data_dir: None,
+            api_token: None,
+            flags: BTreeMap::new(),
+            rate_limits: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Longest accepted shutdown grace period, one day
+    pub const MAX_SHUTDOWN_GRACE_PERIOD_SECS: u64 = 24 * 60 * 60;
+
+    /// Time to wait for running operations to finish on shutdown
+    pub fn shutdown_grace_period(&self) -> Duration {
+        Duration::from_secs(self.shutdown_grace_period_secs)
+    }
+
+    /// Check every field against its constraints, reporting all violations at once
+    pub fn validate(&self) -> Result<()> {
+        let mut violations = Vec::new();
+
+        if !is_identifier(&self.name) {
+            violations.push(Violation::new(
+                "name",
+                &self.name,
+                "must be a valid identifier",
+                "start with an ASCII letter and use only letters, digits and underscores",
+            ));
+        }
+        if self
+            .api_token
+            .as_ref()
+            .is_some_and(|token| token.expose().trim().is_empty())
+        {
+            violations.push(Violation::new(
+                "api_token",
+                &self.api_token,
+                "must not be empty",
+                "unset it or provide the full token",
+            ));
+        }
+        for (name, setting) in &self.flags {
+            if matches!(setting, FlagSetting::Rollout(percent) if *percent > 100) {
+                violations.push(Violation::new(
+                    format!("flags.{name}"),
+                    setting,
+                    "must be a boolean or a percentage from 0 to 100",
+                    "use true, false or the share of keys to enable the flag for",
+                ));
+            }
+        }
+        for (name, limit) in &self.rate_limits {
+            if limit.requests < 1 {
+                violations.push(Violation::new(
+                    format!("rate_limits.{name}.requests"),
+                    limit.requests,
+                    "must be at least 1",
+                    "set it to the number of actions allowed per period",
+                ));
+            }
+            if limit.period_secs < 1 {
+                violations.push(Violation::new(
+                    format!("rate_limits.{name}.period_secs"),
+                    limit.period_secs,
+                    "must be at least 1",
+                    "scale requests up to express sub-second rates",
+                ));
+            }
+            if limit.burst == Some(0) {
+                violations.push(Violation::new(
+                    format!("rate_limits.{name}.burst"),
+                    limit.burst,
+                    "must be at least 1",
+                    "unset it to allow bursts of `requests`",
+                ));
+            }
+        }
+        if self.max_concurrent < 1 {
+            violations.push(Violation::new(
+                "max_concurrent",
+                self.max_concurrent,
+                "must be at least 1",
+                "set it to the number of operations allowed to run at once",
+            ));
+        }
+        if self.shutdown_grace_period_secs > Self::MAX_SHUTDOWN_GRACE_PERIOD_SECS {
+            violations.push(Violation::new(
+                "shutdown_grace_period_secs",
+                self.shutdown_grace_period_secs,
+                format!(
+                    "must be at most {} (one day)",
+                    Self::MAX_SHUTDOWN_GRACE_PERIOD_SECS
+                ),
+                "lower it; a second Ctrl-C stops waiting at any time",
+            ));
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Validation(violations))
         }
     }
 }
 
-/// Application state
-#[derive(Debug)]
-pub struct AppState {
-    /// Current configuration
-    pub config: Config,
-    /// Number of active operations
-    pub active_operations: usize,
+/// Setting of a feature flag
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(untagged)]
+pub enum FlagSetting {
+    /// On or off for everyone
+    Enabled(bool),
+    /// On for this percentage (0-100) of rollout keys
+    Rollout(u8),
 }
 
-impl AppState {
-    /// Create new application state with config
-    pub fn new(config: Config) -> Self {
+impl FlagSetting {
+    /// Parse `on`/`off` (or `true`/`false`, `yes`/`no`) or a percentage such as `25%`
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "on" | "true" | "yes" => Some(Self::Enabled(true)),
+            "off" | "false" | "no" => Some(Self::Enabled(false)),
+            other => other
+                .strip_suffix('%')
+                .unwrap_or(other)
+                .parse()
+                .ok()
+                .filter(|percent| *percent <= 100)
+                .map(Self::Rollout),
+        }
+    }
+}
+
+impl fmt::Display for FlagSetting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Enabled(true) => f.write_str("on"),
+            Self::Enabled(false) => f.write_str("off"),
+            Self::Rollout(percent) => write!(f, "{percent}%"),
+        }
+    }
+}
+
+/// Algorithm enforcing a [`RateLimit`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitAlgorithm {
+    /// Refill permits continuously, allowing bursts up to `burst`
+    #[default]
+    TokenBucket,
+    /// Allow at most `requests` in any window of `period_secs`
+    SlidingWindow,
+}
+
+/// Maximum rate of an action, such as calls to a local service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimit {
+    /// Actions allowed per period
+    #[cfg_attr(feature = "schema", schemars(range(min = 1)))]
+    pub requests: u32,
+    /// Length of the period in seconds
+    #[cfg_attr(feature = "schema", schemars(range(min = 1)))]
+    pub period_secs: u64,
+    /// Actions allowed at once after being idle (token bucket only); defaults to `requests`
+    #[cfg_attr(feature = "schema", schemars(range(min = 1)))]
+    pub burst: Option<u32>,
+    /// How the limit is enforced
+    pub algorithm: RateLimitAlgorithm,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
         Self {
-            config,
-            active_operations: 0,
+            requests: 10,
+            period_secs: 1,
+            burst: None,
+            algorithm: RateLimitAlgorithm::TokenBucket,
         }
     }
-}
\ No newline at end of file
+}
+
+impl RateLimit {
+    /// Length of the period
+    pub fn period(&self) -> Duration {
+        Duration::from_secs(self.period_secs)
+    }
+}
+
+/// Field value of a [`Record`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    /// Missing value
+    Null,
+    /// `true` or `false`
+    Bool(bool),
+    /// Whole number
+    Integer(i64),
+    /// Floating-point number
+    Float(f64),
+    /// Text, also used for values without a type of their own, such as dates
+    String(String),
+    /// Ordered values
+    List(Vec<Value>),
+    /// Values by name
+    Map(BTreeMap<String, Value>),
+}
+
+/// Input record in a representation shared by every input format
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Record {
+    /// Line of the input the record starts on, starting at 1
+    pub line: u64,
+    /// Field values by name
+    pub fields: BTreeMap<String, Value>,
+}
+
+impl Record {
+    /// Create a record without fields starting on `line`
+    pub fn new(line: u64) -> Self {
+        Self {
+            line,
+            fields: BTreeMap::new(),
+        }
+    }
+
+    /// Value of the field `name`
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.fields.get(name)
+    }
+}
+
+/// Text shown instead of a secret value
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Sensitive configuration value, such as a token or password
+///
+/// `Debug`, `Display` and `Serialize` only ever produce [`REDACTED`], so a
+/// secret cannot leak through status output, logs or error messages. The
+/// value is zeroed on drop and only readable through [`Secret::expose`].
+#[derive(Clone)]
+pub struct Secret(SecretString);
+
+impl Secret {
+    /// Wrap a sensitive value
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(SecretString::new(value.into()))
+    }
+
+    /// Read the value; keep the result out of anything that gets printed
+    pub fn expose(&self) -> &str {
+        self.0.expose_secret()
+    }
+}
+
+impl PartialEq for Secret {
+    fn eq(&self, other: &Self) -> bool {
+        self.expose() == other.expose()
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> core::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(SecretVisitor)
+    }
+}
+
+/// Accepts strings, and scalars that environment or CLI coercion produced,
+/// without echoing rejected input in the error
+struct SecretVisitor;
+
+impl Visitor<'_> for SecretVisitor {
+    type Value = Secret;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a secret string")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> core::result::Result<Secret, E> {
+        Ok(Secret::new(value))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> core::result::Result<Secret, E> {
+        Ok(Secret::new(value))
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> core::result::Result<Secret, E> {
+        Ok(Secret::new(value.to_string()))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> core::result::Result<Secret, E> {
+        Ok(Secret::new(value.to_string()))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> core::result::Result<Secret, E> {
+        Ok(Secret::new(value.to_string()))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> core::result::Result<Secret, E> {
+        Ok(Secret::new(value.to_string()))
+    }
+
+    fn visit_bytes<E: de::Error>(self, _: &[u8]) -> core::result::Result<Secret, E> {
+        Err(E::invalid_type(Unexpected::Other("bytes"), &self))
+    }
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for Secret {
+    fn schema_name() -> alloc::borrow::Cow<'static, str> {
+        "Secret".into()
+    }
+
+    fn json_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        schemars::json_schema!({
+            "type": "string",
+            "writeOnly": true,
+        })
+    }
+}
+
+/// Check that `s` starts with an ASCII letter and contains only ASCII alphanumerics and underscores
+///
+/// Kept in sync with the `name` pattern of the JSON Schema.
+fn is_identifier(s: &str) -> bool {
+    s.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config_is_valid() {
+        assert!(Config::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_collects_all_violations() {
+        let mut config = Config {
+            name: "1-bad".to_string(),
+            max_concurrent: 0,
+            shutdown_grace_period_secs: u64::MAX,
+            ..Config::default()
+        };
+        config.rate_limits.insert(
+            "run".to_string(),
+            RateLimit {
+                requests: 0,
+                ..RateLimit::default()
+            },
+        );
+        let Err(Error::Validation(violations)) = config.validate() else {
+            panic!("expected validation error");
+        };
+        let paths: Vec<_> = violations.iter().map(|v| v.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "name",
+                "rate_limits.run.requests",
+                "max_concurrent",
+                "shutdown_grace_period_secs"
+            ]
+        );
+        assert_eq!(violations[0].value, "\"1-bad\"");
+        assert_eq!(violations[2].value, "0");
+    }
+}
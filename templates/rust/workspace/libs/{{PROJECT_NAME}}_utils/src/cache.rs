@@ -0,0 +1,596 @@
+//! In-memory LRU cache with per-entry TTL and optional persistence
+//!
+//! A [`Cache`] holds up to `capacity` entries and evicts the least recently
+//! used one when full. Entries can expire after a time to live. A cache opened
+//! with [`Cache::open`] is backed by a JSON file, by default in the user cache
+//! directory, and written back by [`Cache::save`] or when it is dropped.
+//! Hit and miss counters are persisted with the entries.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::{Deserialize, Serialize};
+use {{PROJECT_NAME}}_core::{Error, Result};
+
+use crate::clock::{Clock, SystemClock};
+use crate::logging;
+
+/// Version of the cache file format
+const FORMAT_VERSION: u64 = 1;
+
+/// Directory for persistent caches, `{{PROJECT_NAME}}` in the user cache directory
+pub fn default_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("{{PROJECT_NAME}}"))
+}
+
+/// Name of the persistent cache stored at `path`, if it is a cache file
+fn cache_name(path: &Path) -> Option<String> {
+    if path.extension()? != "json" {
+        return None;
+    }
+    Some(path.file_stem()?.to_string_lossy().into_owned())
+}
+
+/// Every readable persistent cache in `dir` with its statistics, sorted by name
+///
+/// Expired entries are not counted.
+pub fn list(dir: &Path) -> Result<Vec<(String, CacheStats)>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let now = unix_ms(SystemTime::now());
+    let mut caches = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let Some(name) = cache_name(&path) else {
+            continue;
+        };
+        let Some(stored) = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str::<StoredCache<IgnoredAny>>(&contents).ok())
+            .filter(|stored| stored.version == FORMAT_VERSION)
+        else {
+            continue;
+        };
+        let entries = stored
+            .entries
+            .iter()
+            .filter(|entry| entry.expires_at_ms.is_none_or(|at| at > now))
+            .count();
+        caches.push((
+            name,
+            CacheStats {
+                entries,
+                ..stored.stats
+            },
+        ));
+    }
+    caches.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(caches)
+}
+
+/// Delete every persistent cache in `dir`, returning their names sorted
+pub fn clear_all(dir: &Path) -> Result<Vec<String>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut removed = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if let Some(name) = cache_name(&path) {
+            fs::remove_file(&path)?;
+            removed.push(name);
+        }
+    }
+    removed.sort();
+    Ok(removed)
+}
+
+/// Hit, miss and eviction counters of a cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheStats {
+    /// Entries currently cached
+    pub entries: usize,
+    /// Lookups that found a live entry
+    pub hits: u64,
+    /// Lookups that found nothing or an expired entry
+    pub misses: u64,
+    /// Entries removed to make room
+    pub evictions: u64,
+    /// Entries removed because their TTL passed
+    pub expirations: u64,
+}
+
+impl CacheStats {
+    /// Share of lookups that were hits, or `None` before the first lookup
+    pub fn hit_rate(&self) -> Option<f64> {
+        let lookups = self.hits + self.misses;
+        (lookups > 0).then(|| self.hits as f64 / lookups as f64)
+    }
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} entries, {} hits, {} misses",
+            self.entries, self.hits, self.misses
+        )?;
+        if let Some(rate) = self.hit_rate() {
+            write!(f, " ({:.1}% hit rate)", rate * 100.0)?;
+        }
+        write!(
+            f,
+            ", {} evictions, {} expirations",
+            self.evictions, self.expirations
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Entry<V> {
+    value: V,
+    expires_at: Option<Instant>,
+    tick: u64,
+}
+
+#[derive(Debug)]
+struct Inner<V> {
+    entries: HashMap<String, Entry<V>>,
+    /// Keys by the tick of their last use, least recent first
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+    stats: CacheStats,
+    dirty: bool,
+}
+
+impl<V> Inner<V> {
+    fn touch(&mut self, key: &str) {
+        self.tick += 1;
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.recency.remove(&entry.tick);
+            entry.tick = self.tick;
+            self.recency.insert(self.tick, key.to_string());
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.recency.remove(&entry.tick);
+        Some(entry.value)
+    }
+}
+
+/// Thread-safe LRU cache with string keys
+pub struct Cache<V> {
+    capacity: usize,
+    ttl: Option<Duration>,
+    clock: Arc<dyn Clock>,
+    path: Option<PathBuf>,
+    /// Saves on drop; set by [`Cache::open`], where `V` is known to be serializable
+    flush: Option<fn(&Self)>,
+    inner: Mutex<Inner<V>>,
+}
+
+impl<V> fmt::Debug for Cache<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cache")
+            .field("capacity", &self.capacity)
+            .field("ttl", &self.ttl)
+            .field("path", &self.path)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl<V> Cache<V> {
+    /// Create an in-memory cache holding up to `capacity` entries
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            ttl: None,
+            clock: Arc::new(SystemClock),
+            path: None,
+            flush: None,
+            inner: Mutex::new(Inner {
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                tick: 0,
+                stats: CacheStats::default(),
+                dirty: false,
+            }),
+        }
+    }
+
+    /// Expire entries `ttl` after they were inserted, unless inserted with their own TTL
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Read time from `clock`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn inner(&self) -> MutexGuard<'_, Inner<V>> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Backing file, if the cache is persistent
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Cached value for `key`, marking it as recently used
+    pub fn get(&self, key: &str) -> Option<V>
+    where
+        V: Clone,
+    {
+        let now = self.clock.now();
+        let mut inner = self.inner();
+        inner.dirty = true;
+        let expired = match inner.entries.get(key) {
+            None => {
+                inner.stats.misses += 1;
+                return None;
+            }
+            Some(entry) => entry.expires_at.is_some_and(|at| at <= now),
+        };
+        if expired {
+            inner.remove(key);
+            inner.stats.expirations += 1;
+            inner.stats.misses += 1;
+            return None;
+        }
+        inner.stats.hits += 1;
+        inner.touch(key);
+        inner.entries.get(key).map(|entry| entry.value.clone())
+    }
+
+    /// Cache `value` under `key` with the default TTL
+    pub fn insert(&self, key: impl Into<String>, value: V) {
+        self.insert_with_ttl(key, value, self.ttl);
+    }
+
+    /// Cache `value` under `key`, expiring after `ttl` if given
+    pub fn insert_with_ttl(&self, key: impl Into<String>, value: V, ttl: Option<Duration>) {
+        let key = key.into();
+        // A time to live beyond what an `Instant` can represent never expires
+        let expires_at = ttl.and_then(|ttl| self.clock.now().checked_add(ttl));
+        let mut inner = self.inner();
+        inner.dirty = true;
+        inner.remove(&key);
+        while inner.entries.len() >= self.capacity {
+            let Some((_, oldest)) = inner.recency.pop_first() else {
+                break;
+            };
+            inner.entries.remove(&oldest);
+            inner.stats.evictions += 1;
+        }
+        inner.entries.insert(
+            key.clone(),
+            Entry {
+                value,
+                expires_at,
+                tick: 0,
+            },
+        );
+        inner.touch(&key);
+    }
+
+    /// Cached value for `key`, computing and caching it on a miss
+    pub fn get_or_insert_with(&self, key: &str, compute: impl FnOnce() -> V) -> V
+    where
+        V: Clone,
+    {
+        if let Some(value) = self.get(key) {
+            return value;
+        }
+        let value = compute();
+        self.insert(key, value.clone());
+        value
+    }
+
+    /// Remove `key`, returning its value
+    pub fn remove(&self, key: &str) -> Option<V> {
+        let mut inner = self.inner();
+        inner.dirty = true;
+        inner.remove(key)
+    }
+
+    /// Remove every entry and reset the statistics
+    pub fn clear(&self) {
+        let mut inner = self.inner();
+        inner.entries.clear();
+        inner.recency.clear();
+        inner.stats = CacheStats::default();
+        inner.dirty = true;
+    }
+
+    /// Number of cached entries, including expired ones not yet removed
+    pub fn len(&self) -> usize {
+        self.inner().entries.len()
+    }
+
+    /// Whether the cache holds no entries
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Current counters
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.inner();
+        CacheStats {
+            entries: inner.entries.len(),
+            ..inner.stats
+        }
+    }
+}
+
+/// On-disk form of an entry; expiry is wall-clock time since it must survive restarts
+#[derive(Serialize, Deserialize)]
+struct StoredEntry<V> {
+    key: String,
+    value: V,
+    expires_at_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredCache<V> {
+    version: u64,
+    stats: CacheStats,
+    /// Least recently used first
+    entries: Vec<StoredEntry<V>>,
+}
+
+fn unix_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+        .try_into()
+        .unwrap_or(u64::MAX)
+}
+
+impl<V: Serialize + DeserializeOwned> Cache<V> {
+    /// Open the persistent cache `name` in [`default_dir`]
+    pub fn open_default(name: &str, capacity: usize) -> Result<Self> {
+        let dir = default_dir()
+            .ok_or_else(|| Error::Config("no cache directory on this platform".to_string()))?;
+        Self::open(dir.join(format!("{name}.json")), capacity)
+    }
+
+    /// Open a cache backed by the file at `path`, loading its live entries
+    ///
+    /// A missing, unreadable or corrupt file yields an empty cache, since the
+    /// contents can always be recomputed.
+    pub fn open(path: impl Into<PathBuf>, capacity: usize) -> Result<Self> {
+        let mut cache = Self::new(capacity);
+        let path = path.into();
+        let stored = fs::read(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_slice::<StoredCache<V>>(&contents).ok())
+            .filter(|stored| stored.version == FORMAT_VERSION);
+        if let Some(stored) = stored {
+            let now = unix_ms(SystemTime::now());
+            let instant = cache.clock.now();
+            cache.inner().stats = stored.stats;
+            for entry in stored.entries {
+                let expires_at = match entry.expires_at_ms {
+                    Some(at) if at <= now => continue,
+                    Some(at) => instant.checked_add(Duration::from_millis(at - now)),
+                    None => None,
+                };
+                let mut inner = cache.inner();
+                inner.entries.insert(
+                    entry.key.clone(),
+                    Entry {
+                        value: entry.value,
+                        expires_at,
+                        tick: 0,
+                    },
+                );
+                inner.touch(&entry.key);
+            }
+            let mut inner = cache.inner();
+            while inner.entries.len() > cache.capacity {
+                if let Some((_, oldest)) = inner.recency.pop_first() {
+                    inner.entries.remove(&oldest);
+                }
+            }
+            inner.dirty = false;
+        }
+        cache.path = Some(path);
+        cache.flush = Some(|cache| {
+            if let Err(e) = cache.save() {
+                logging::error(&format!("Failed to save cache: {e}"));
+            }
+        });
+        Ok(cache)
+    }
+
+    /// Write live entries and statistics to the backing file, atomically
+    ///
+    /// Does nothing for an in-memory cache.
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let now = self.clock.now();
+        let wall = SystemTime::now();
+        let mut inner = self.inner();
+        let entries = inner
+            .recency
+            .values()
+            .filter_map(|key| {
+                let entry = &inner.entries[key];
+                let expires_at_ms = match entry.expires_at {
+                    Some(at) if at <= now => return None,
+                    Some(at) => wall.checked_add(at - now).map(unix_ms),
+                    None => None,
+                };
+                Some(StoredEntry {
+                    key: key.clone(),
+                    value: &entry.value,
+                    expires_at_ms,
+                })
+            })
+            .collect();
+        let stored = StoredCache {
+            version: FORMAT_VERSION,
+            stats: inner.stats,
+            entries,
+        };
+        let contents = serde_json::to_string(&stored).map_err(|e| Error::Other(e.to_string()))?;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut temporary = path.clone().into_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, contents)?;
+        fs::rename(&temporary, path)?;
+        inner.dirty = false;
+        Ok(())
+    }
+}
+
+impl<V> Drop for Cache<V> {
+    fn drop(&mut self) {
+        let dirty = self.inner().dirty;
+        if let Some(flush) = self.flush.filter(|_| dirty) {
+            flush(self);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = Cache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        assert_eq!(cache.get("a"), Some(1));
+        cache.insert("c", 3);
+
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("a"), Some(1));
+        assert_eq!(cache.get("c"), Some(3));
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                entries: 2,
+                hits: 3,
+                misses: 1,
+                evictions: 1,
+                expirations: 0,
+            }
+        );
+        assert_eq!(cache.stats().hit_rate(), Some(0.75));
+    }
+
+    #[test]
+    fn test_entries_expire() {
+        let clock = ManualClock::new();
+        let cache = Cache::new(10)
+            .with_ttl(Duration::from_secs(60))
+            .with_clock(Arc::new(clock.clone()));
+        cache.insert("default", "a");
+        cache.insert_with_ttl("short", "b", Some(Duration::from_secs(5)));
+        cache.insert_with_ttl("forever", "c", None);
+        cache.insert_with_ttl("unbounded", "u", Some(Duration::MAX));
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(cache.get("short"), None);
+        assert_eq!(cache.get("default"), Some("a"));
+        clock.advance(Duration::from_secs(55));
+        assert_eq!(cache.get("default"), None);
+        assert_eq!(cache.get("forever"), Some("c"));
+        assert_eq!(cache.get("unbounded"), Some("u"));
+        assert_eq!(cache.stats().expirations, 2);
+
+        let computed = cache.get_or_insert_with("default", || "d");
+        assert_eq!(computed, "d");
+        assert_eq!(cache.get_or_insert_with("default", || "e"), "d");
+    }
+
+    #[test]
+    fn test_persists_entries_and_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("lookups.json");
+        {
+            let cache = Cache::open(&path, 2).unwrap();
+            cache.insert("old", "x".to_string());
+            cache.insert("kept", "y".to_string());
+            cache.insert_with_ttl("gone", "z".to_string(), Some(Duration::ZERO));
+            assert_eq!(cache.get("kept"), Some("y".to_string()));
+            // Dropped here, which saves the cache
+        }
+
+        let cache: Cache<String> = Cache::open(&path, 2).unwrap();
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get("kept"), Some("y".to_string()));
+        assert_eq!(cache.stats().hits, 2);
+        assert_eq!(cache.stats().evictions, 1);
+
+        cache.clear();
+        cache.save().unwrap();
+        let cache: Cache<String> = Cache::open(&path, 2).unwrap();
+        assert!(cache.is_empty());
+        assert_eq!(cache.stats(), CacheStats::default());
+    }
+
+    #[test]
+    fn test_corrupt_file_yields_empty_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.json");
+        for contents in [&b"{not json"[..], b"\xff\xfe"] {
+            fs::write(&path, contents).unwrap();
+            let cache: Cache<u32> = Cache::open(&path, 4).unwrap();
+            assert!(cache.is_empty());
+        }
+        let cache: Cache<u32> = Cache::open(&path, 4).unwrap();
+        cache.insert("a", 1);
+        cache.save().unwrap();
+        assert_eq!(Cache::<u32>::open(&path, 4).unwrap().get("a"), Some(1));
+    }
+
+    #[test]
+    fn test_list_and_clear_all() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["users", "licenses"] {
+            let cache = Cache::open(dir.path().join(format!("{name}.json")), 8).unwrap();
+            cache.insert("key", name.to_string());
+            cache.get("key");
+            cache.get("missing");
+        }
+        fs::write(dir.path().join("notes.txt"), "keep").unwrap();
+
+        let caches = list(dir.path()).unwrap();
+        let names: Vec<_> = caches.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["licenses", "users"]);
+        assert_eq!(caches[0].1.entries, 1);
+        assert_eq!(caches[0].1.hit_rate(), Some(0.5));
+
+        assert_eq!(clear_all(dir.path()).unwrap(), ["licenses", "users"]);
+        assert!(list(dir.path()).unwrap().is_empty());
+        assert!(dir.path().join("notes.txt").exists());
+        assert!(list(&dir.path().join("missing")).unwrap().is_empty());
+    }
+}
@@ -7,7 +7,11 @@
 #![warn(missing_docs)]
 #![warn(clippy::all)]
 
+pub mod cache;
+pub mod clock;
 pub mod logging;
+pub mod rate_limit;
+pub mod retry;
 pub mod string;
 
 /// Initialize utilities
@@ -24,4 +28,4 @@ mod tests {
     fn test_init() {
         assert!(init().is_ok());
     }
-}
\ No newline at end of file
+}
@@ -1,20 +1,28 @@
 //! Logging utilities
 
 use anyhow::Result;
+use {{PROJECT_NAME}}_core::events::EventBus;
 
 /// Initialize logging for the application
 pub fn init() -> Result<()> {
     // In a real application, you might use tracing-subscriber here
-    println!("Logging initialized");
+    eprintln!("Logging initialized");
     Ok(())
 }
 
 /// Log a message at info level
+///
+/// Logs go to stderr, keeping stdout free for command output.
 pub fn info(msg: &str) {
-    println!("[INFO] {}", msg);
+    eprintln!("[INFO] {}", msg);
 }
 
 /// Log a message at error level
 pub fn error(msg: &str) {
     eprintln!("[ERROR] {}", msg);
-}
\ No newline at end of file
+}
+
+/// Log every event published on `events` at info level
+pub fn log_events(events: &EventBus) {
+    events.on_event(|event| info(&event.to_string()));
+}
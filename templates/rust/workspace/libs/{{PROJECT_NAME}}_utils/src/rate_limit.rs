@@ -0,0 +1,270 @@
+//! Per-key rate limiting
+//!
+//! [`TokenBucket`] and [`SlidingWindow`] limit how often an action may happen
+//! for each key, e.g. per service or per tenant. Both are safe to share across
+//! threads and read time from an injectable [`Clock`], so tests can use a
+//! [`ManualClock`] instead of sleeping.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use {{PROJECT_NAME}}_core::types::{RateLimit, RateLimitAlgorithm};
+
+pub use crate::clock::{Clock, ManualClock, SystemClock};
+
+/// Limiter deciding whether an action for a key may happen now
+pub trait RateLimiter: Send + Sync {
+    /// Take a permit for `key`, or return how long until one may be available
+    fn try_acquire(&self, key: &str) -> Result<(), Duration>;
+
+    /// Block until a permit for `key` is taken
+    ///
+    /// Waits on the system clock, so use [`RateLimiter::try_acquire`] with a
+    /// [`ManualClock`].
+    fn acquire(&self, key: &str) {
+        while let Err(wait) = self.try_acquire(key) {
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// Wait asynchronously until a permit for `key` is taken
+    #[cfg(feature = "async")]
+    fn acquire_async<'a>(&'a self, key: &'a str) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+        Box::pin(async move {
+            while let Err(wait) = self.try_acquire(key) {
+                tokio::time::sleep(wait).await;
+            }
+        })
+    }
+}
+
+/// Create the limiter described by `limit`
+pub fn from_config(limit: &RateLimit, clock: Arc<dyn Clock>) -> Arc<dyn RateLimiter> {
+    match limit.algorithm {
+        RateLimitAlgorithm::TokenBucket => Arc::new(
+            TokenBucket::new(
+                limit.burst.unwrap_or(limit.requests),
+                limit.requests,
+                limit.period(),
+            )
+            .with_clock(clock),
+        ),
+        RateLimitAlgorithm::SlidingWindow => {
+            Arc::new(SlidingWindow::new(limit.requests, limit.period()).with_clock(clock))
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Permits refill continuously; an idle key can burst up to the capacity
+pub struct TokenBucket {
+    capacity: f64,
+    per_second: f64,
+    clock: Arc<dyn Clock>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl fmt::Debug for TokenBucket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenBucket")
+            .field("capacity", &self.capacity)
+            .field("per_second", &self.per_second)
+            .field("keys", &lock(&self.buckets).len())
+            .finish()
+    }
+}
+
+impl TokenBucket {
+    /// Allow bursts of `capacity`, refilling `refill` permits every `period`
+    pub fn new(capacity: u32, refill: u32, period: Duration) -> Self {
+        let period = period.max(Duration::from_nanos(1));
+        Self {
+            capacity: f64::from(capacity.max(1)),
+            per_second: f64::from(refill.max(1)) / period.as_secs_f64(),
+            clock: Arc::new(SystemClock),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Read time from `clock`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+impl RateLimiter for TokenBucket {
+    fn try_acquire(&self, key: &str) -> Result<(), Duration> {
+        let now = self.clock.now();
+        let mut buckets = lock(&self.buckets);
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.capacity,
+            updated: now,
+        });
+        let refilled =
+            now.saturating_duration_since(bucket.updated).as_secs_f64() * self.per_second;
+        bucket.tokens = (bucket.tokens + refilled).min(self.capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.per_second,
+            ))
+        }
+    }
+}
+
+/// At most `limit` permits in any window of the given length
+pub struct SlidingWindow {
+    limit: usize,
+    window: Duration,
+    clock: Arc<dyn Clock>,
+    taken: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl fmt::Debug for SlidingWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SlidingWindow")
+            .field("limit", &self.limit)
+            .field("window", &self.window)
+            .field("keys", &lock(&self.taken).len())
+            .finish()
+    }
+}
+
+impl SlidingWindow {
+    /// Allow `limit` permits per `window`
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit: limit.max(1) as usize,
+            window,
+            clock: Arc::new(SystemClock),
+            taken: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Read time from `clock`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+impl RateLimiter for SlidingWindow {
+    fn try_acquire(&self, key: &str) -> Result<(), Duration> {
+        let now = self.clock.now();
+        let mut taken = lock(&self.taken);
+        let times = taken.entry(key.to_string()).or_default();
+        while times
+            .front()
+            .is_some_and(|time| now.saturating_duration_since(*time) >= self.window)
+        {
+            times.pop_front();
+        }
+        match times.front() {
+            Some(oldest) if times.len() >= self.limit => {
+                Err(self.window - now.saturating_duration_since(*oldest))
+            }
+            _ => {
+                times.push_back(now);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket_bursts_and_refills() {
+        let clock = ManualClock::new();
+        let bucket =
+            TokenBucket::new(3, 1, Duration::from_secs(1)).with_clock(Arc::new(clock.clone()));
+        for _ in 0..3 {
+            assert!(bucket.try_acquire("db").is_ok());
+        }
+        assert_eq!(bucket.try_acquire("db"), Err(Duration::from_secs(1)));
+        // Keys are limited independently
+        assert!(bucket.try_acquire("cache").is_ok());
+
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(bucket.try_acquire("db"), Err(Duration::from_millis(500)));
+        clock.advance(Duration::from_millis(500));
+        assert!(bucket.try_acquire("db").is_ok());
+
+        clock.advance(Duration::from_secs(60));
+        for _ in 0..3 {
+            assert!(bucket.try_acquire("db").is_ok());
+        }
+        assert!(bucket.try_acquire("db").is_err());
+    }
+
+    #[test]
+    fn test_sliding_window() {
+        let clock = ManualClock::new();
+        let window =
+            SlidingWindow::new(2, Duration::from_secs(10)).with_clock(Arc::new(clock.clone()));
+        assert!(window.try_acquire("api").is_ok());
+        clock.advance(Duration::from_secs(4));
+        assert!(window.try_acquire("api").is_ok());
+        assert_eq!(window.try_acquire("api"), Err(Duration::from_secs(6)));
+
+        clock.advance(Duration::from_secs(6));
+        assert!(window.try_acquire("api").is_ok());
+        assert_eq!(window.try_acquire("api"), Err(Duration::from_secs(4)));
+    }
+
+    #[test]
+    fn test_from_config_is_shared_across_threads() {
+        let clock = ManualClock::new();
+        let limit = RateLimit {
+            requests: 5,
+            period_secs: 60,
+            algorithm: RateLimitAlgorithm::SlidingWindow,
+            ..RateLimit::default()
+        };
+        let limiter = from_config(&limit, Arc::new(clock));
+        let granted: usize = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        (0..5)
+                            .filter(|_| limiter.try_acquire("run").is_ok())
+                            .count()
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|w| w.join().unwrap()).sum()
+        });
+        assert_eq!(granted, 5);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_acquire_async_waits_for_refill() {
+        let bucket = TokenBucket::new(1, 1, Duration::from_millis(20));
+        let started = Instant::now();
+        bucket.acquire_async("job").await;
+        bucket.acquire_async("job").await;
+        assert!(started.elapsed() >= Duration::from_millis(15));
+    }
+}
@@ -0,0 +1,394 @@
+//! Retrying fallible operations
+//!
+//! A [`RetryPolicy`] bounds how often and for how long an operation is retried
+//! and how long to wait in between. [`Retry`] runs an operation under a policy,
+//! retrying only errors classified as transient and reporting every failed
+//! attempt to its hooks.
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::io::{self, ErrorKind};
+use std::time::{Duration, Instant};
+
+use {{PROJECT_NAME}}_core::Error;
+pub use {{PROJECT_NAME}}_core::jobs::Backoff;
+
+use crate::logging;
+
+/// Randomization applied to backoff delays so that clients do not retry in lockstep
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Jitter {
+    /// Use the backoff delay as is
+    None,
+    /// Wait a random time between zero and the delay
+    #[default]
+    Full,
+    /// Wait half the delay plus a random time up to the other half
+    Equal,
+}
+
+impl Jitter {
+    /// Randomize `delay`
+    pub fn apply(self, delay: Duration) -> Duration {
+        match self {
+            Self::None => delay,
+            Self::Full => delay.mul_f64(random_fraction()),
+            Self::Equal => delay / 2 + (delay / 2).mul_f64(random_fraction()),
+        }
+    }
+}
+
+/// Uniformly distributed value in `[0, 1)`, seeded per call from the OS
+fn random_fraction() -> f64 {
+    let bits = RandomState::new().hash_one(Instant::now());
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// How often, how long and how far apart an operation is retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first
+    pub max_attempts: u32,
+    /// Delay between attempts
+    pub backoff: Backoff,
+    /// Randomization of the delay
+    pub jitter: Jitter,
+    /// Give up instead of waiting past this time since the first attempt
+    pub max_elapsed: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            backoff: Backoff::Exponential {
+                initial: Duration::from_millis(100),
+                max: Duration::from_secs(10),
+            },
+            jitter: Jitter::Full,
+            max_elapsed: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Make `max_attempts` attempts, waiting `delay` in between
+    pub fn fixed(max_attempts: u32, delay: Duration) -> Self {
+        Self {
+            max_attempts,
+            backoff: Backoff::Fixed(delay),
+            jitter: Jitter::None,
+            max_elapsed: None,
+        }
+    }
+
+    /// Make `max_attempts` attempts, doubling the wait from `initial` up to `max`
+    pub fn exponential(max_attempts: u32, initial: Duration, max: Duration) -> Self {
+        Self {
+            max_attempts,
+            backoff: Backoff::Exponential { initial, max },
+            ..Self::default()
+        }
+    }
+
+    /// Wait before the next attempt, or `None` to give up
+    ///
+    /// `attempt` is the number of attempts made so far.
+    pub fn next_delay(&self, attempt: u32, elapsed: Duration) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        let delay = self.jitter.apply(self.backoff.delay(attempt));
+        match self.max_elapsed {
+            Some(max) if elapsed + delay > max => None,
+            _ => Some(delay),
+        }
+    }
+}
+
+/// Errors that can tell whether trying again might succeed
+pub trait Retryable {
+    /// Whether the failure is transient
+    fn is_retryable(&self) -> bool;
+}
+
+impl Retryable for io::Error {
+    fn is_retryable(&self) -> bool {
+        matches!(
+            self.kind(),
+            ErrorKind::Interrupted
+                | ErrorKind::WouldBlock
+                | ErrorKind::TimedOut
+                | ErrorKind::ConnectionRefused
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::NotConnected
+                | ErrorKind::BrokenPipe
+        )
+    }
+}
+
+/// Only transient IO failures are retried; configuration, usage and data errors never are
+impl Retryable for Error {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Io(e) => e.is_retryable(),
+            _ => false,
+        }
+    }
+}
+
+impl Retryable for anyhow::Error {
+    fn is_retryable(&self) -> bool {
+        if let Some(e) = self.downcast_ref::<Error>() {
+            e.is_retryable()
+        } else if let Some(e) = self.downcast_ref::<io::Error>() {
+            e.is_retryable()
+        } else {
+            false
+        }
+    }
+}
+
+/// Failed attempt reported to the hooks of a [`Retry`]
+#[derive(Debug)]
+pub struct Attempt<'a, E> {
+    /// Number of the attempt, starting at 1
+    pub number: u32,
+    /// Error the attempt failed with
+    pub error: &'a E,
+    /// Wait before the next attempt, or `None` if this was the last one
+    pub next_delay: Option<Duration>,
+    /// Time since the first attempt started
+    pub elapsed: Duration,
+}
+
+type Classifier<E> = Box<dyn Fn(&E) -> bool + Send + Sync>;
+type Hook<E> = Box<dyn Fn(&Attempt<'_, E>) + Send + Sync>;
+
+/// Runs operations under a [`RetryPolicy`]
+pub struct Retry<E> {
+    policy: RetryPolicy,
+    classify: Classifier<E>,
+    hooks: Vec<Hook<E>>,
+}
+
+impl<E> fmt::Debug for Retry<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Retry")
+            .field("policy", &self.policy)
+            .field("hooks", &self.hooks.len())
+            .finish()
+    }
+}
+
+impl<E: Retryable + 'static> Retry<E> {
+    /// Retry errors that report themselves as [`Retryable`]
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self::with_classifier(policy, E::is_retryable)
+    }
+}
+
+impl<E> Retry<E> {
+    /// Retry errors for which `classify` returns true
+    pub fn with_classifier(
+        policy: RetryPolicy,
+        classify: impl Fn(&E) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            policy,
+            classify: Box::new(classify),
+            hooks: Vec::new(),
+        }
+    }
+
+    /// Call `hook` after every failed attempt, including the last one
+    pub fn on_attempt(mut self, hook: impl Fn(&Attempt<'_, E>) + Send + Sync + 'static) -> Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Log every failed attempt of the operation `name`
+    pub fn logged(self, name: impl Into<String>) -> Self
+    where
+        E: fmt::Display,
+    {
+        let name = name.into();
+        self.on_attempt(move |attempt| match attempt.next_delay {
+            Some(delay) => logging::info(&format!(
+                "{} failed (attempt {}): {}; retrying in {:?}",
+                name, attempt.number, attempt.error, delay
+            )),
+            None => logging::error(&format!(
+                "{} failed (attempt {}): {}; giving up",
+                name, attempt.number, attempt.error
+            )),
+        })
+    }
+
+    /// Wait before the next attempt after `error`, or `None` to give up
+    fn after_failure(&self, number: u32, error: &E, started: Instant) -> Option<Duration> {
+        let elapsed = started.elapsed();
+        let next_delay = if (self.classify)(error) {
+            self.policy.next_delay(number, elapsed)
+        } else {
+            None
+        };
+        let attempt = Attempt {
+            number,
+            error,
+            next_delay,
+            elapsed,
+        };
+        for hook in &self.hooks {
+            hook(&attempt);
+        }
+        next_delay
+    }
+
+    /// Run `operation`, passing the attempt number, until it succeeds or the policy gives up
+    pub fn run<T>(&self, mut operation: impl FnMut(u32) -> Result<T, E>) -> Result<T, E> {
+        let started = Instant::now();
+        let mut number = 1;
+        loop {
+            match operation(number) {
+                Ok(value) => return Ok(value),
+                Err(error) => match self.after_failure(number, &error, started) {
+                    Some(delay) => std::thread::sleep(delay),
+                    None => return Err(error),
+                },
+            }
+            number += 1;
+        }
+    }
+
+    /// Async [`Retry::run`]; waits without blocking the runtime
+    #[cfg(feature = "async")]
+    pub async fn run_async<T, F, Fut>(&self, mut operation: F) -> Result<T, E>
+    where
+        F: FnMut(u32) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let started = Instant::now();
+        let mut number = 1;
+        loop {
+            match operation(number).await {
+                Ok(value) => return Ok(value),
+                Err(error) => match self.after_failure(number, &error, started) {
+                    Some(delay) => tokio::time::sleep(delay).await,
+                    None => return Err(error),
+                },
+            }
+            number += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn transient() -> Error {
+        Error::Io(io::Error::from(ErrorKind::ConnectionReset))
+    }
+
+    #[test]
+    fn test_policy_delays() {
+        let policy = RetryPolicy {
+            jitter: Jitter::None,
+            ..RetryPolicy::exponential(4, Duration::from_millis(10), Duration::from_millis(25))
+        };
+        let delays: Vec<_> = (1..=4)
+            .map(|attempt| policy.next_delay(attempt, Duration::ZERO))
+            .collect();
+        assert_eq!(
+            delays,
+            [
+                Some(Duration::from_millis(10)),
+                Some(Duration::from_millis(20)),
+                Some(Duration::from_millis(25)),
+                None
+            ]
+        );
+
+        let bounded = RetryPolicy {
+            max_elapsed: Some(Duration::from_millis(15)),
+            ..RetryPolicy::fixed(10, Duration::from_millis(10))
+        };
+        assert!(bounded.next_delay(1, Duration::from_millis(5)).is_some());
+        assert!(bounded.next_delay(1, Duration::from_millis(6)).is_none());
+
+        for _ in 0..100 {
+            let delay = Duration::from_millis(100);
+            assert!(Jitter::Full.apply(delay) <= delay);
+            assert!(Jitter::Equal.apply(delay) >= delay / 2);
+        }
+    }
+
+    #[test]
+    fn test_classification() {
+        assert!(transient().is_retryable());
+        assert!(!Error::Config("bad".to_string()).is_retryable());
+        assert!(!Error::Io(io::Error::from(ErrorKind::NotFound)).is_retryable());
+        assert!(anyhow::Error::from(transient()).is_retryable());
+        assert!(!anyhow::anyhow!("boom").is_retryable());
+    }
+
+    #[test]
+    fn test_retries_transient_errors_and_reports_attempts() {
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&attempts);
+        let retry = Retry::new(RetryPolicy::fixed(3, Duration::ZERO)).on_attempt(move |attempt| {
+            recorded
+                .lock()
+                .unwrap()
+                .push((attempt.number, attempt.next_delay.is_some()));
+        });
+
+        let value = retry
+            .run(|attempt| {
+                if attempt < 3 {
+                    Err(transient())
+                } else {
+                    Ok(attempt)
+                }
+            })
+            .unwrap();
+        assert_eq!(value, 3);
+        assert_eq!(*attempts.lock().unwrap(), [(1, true), (2, true)]);
+
+        attempts.lock().unwrap().clear();
+        let result: Result<(), _> = retry.run(|_| Err(Error::Usage("bad".to_string())));
+        assert!(matches!(result, Err(Error::Usage(_))));
+        assert_eq!(*attempts.lock().unwrap(), [(1, false)]);
+
+        attempts.lock().unwrap().clear();
+        assert!(retry.run(|_| Err::<(), _>(transient())).is_err());
+        assert_eq!(attempts.lock().unwrap().len(), 3);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_run_async_with_classifier() {
+        let retry = Retry::with_classifier(
+            RetryPolicy::fixed(5, Duration::from_millis(1)),
+            |e: &String| e.starts_with("busy"),
+        );
+        let value = retry
+            .run_async(|attempt| async move {
+                if attempt < 2 {
+                    Err("busy".to_string())
+                } else {
+                    Ok(attempt)
+                }
+            })
+            .await;
+        assert_eq!(value, Ok(2));
+        let failed = retry.run_async(|_| async { Err::<u32, _>("fatal".to_string()) });
+        assert_eq!(failed.await, Err("fatal".to_string()));
+    }
+}
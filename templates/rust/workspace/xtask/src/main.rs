@@ -6,6 +6,9 @@ use anyhow::Result;
 use std::env;
 use std::process::Command;
 
+/// Target without a standard library, so a stray `std` use fails to build
+const NO_STD_TARGET: &str = "thumbv7em-none-eabihf";
+
 fn main() -> Result<()> {
     let task = env::args().nth(1);
     match task.as_deref() {
@@ -14,6 +17,7 @@ fn main() -> Result<()> {
         Some("lint") => lint(),
         Some("test") => test(),
         Some("build") => build(),
+        Some("no-std") => no_std(),
         Some("clean") => clean(),
         _ => print_help(),
     }
@@ -31,6 +35,7 @@ fn print_help() -> Result<()> {
     println!("    lint    Run clippy lints");
     println!("    test    Run tests");
     println!("    build   Build all crates");
+    println!("    no-std  Check that {{PROJECT_NAME}}_core builds for a target without std");
     println!("    clean   Clean build artifacts");
     Ok(())
 }
@@ -40,6 +45,7 @@ fn ci() -> Result<()> {
     fmt()?;
     lint()?;
     test()?;
+    no_std()?;
     build()?;
     println!("CI pipeline completed successfully!");
     Ok(())
@@ -93,6 +99,46 @@ fn build() -> Result<()> {
     Ok(())
 }
 
+fn no_std() -> Result<()> {
+    println!("Checking no_std build...");
+    let status = Command::new("cargo")
+        .args([
+            "clippy",
+            "--package",
+            "{{PROJECT_NAME}}_core",
+            "--no-default-features",
+            "--all-targets",
+            "--",
+            "-D",
+            "warnings",
+        ])
+        .status()?;
+
+    if !status.success() {
+        anyhow::bail!("no_std lints of {{PROJECT_NAME}}_core failed");
+    }
+
+    // The host target ships std, so only a bare-metal build proves it is unused
+    let status = Command::new("cargo")
+        .args([
+            "build",
+            "--package",
+            "{{PROJECT_NAME}}_core",
+            "--no-default-features",
+            "--target",
+            NO_STD_TARGET,
+        ])
+        .status()?;
+
+    if !status.success() {
+        anyhow::bail!(
+            "no_std build of {{PROJECT_NAME}}_core for {NO_STD_TARGET} failed \
+             (install the target with `rustup target add {NO_STD_TARGET}`)"
+        );
+    }
+    Ok(())
+}
+
 fn clean() -> Result<()> {
     println!("Cleaning build artifacts...");
     let status = Command::new("cargo")